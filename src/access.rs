@@ -0,0 +1,57 @@
+use crate::config::Config;
+use anyhow::{Context, Result};
+use log::info;
+use std::collections::HashMap;
+use std::fs::canonicalize;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+const GRANT_LIFETIME: Duration = Duration::from_secs(10 * 60);
+
+struct Grant {
+    expiry: Instant,
+    // Copied directories are shared with their contents, files only by their exact path.
+    subtree: bool,
+}
+
+static GRANTS: LazyLock<Mutex<HashMap<PathBuf, Grant>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+pub fn grant_path<P: AsRef<Path>>(path: P) -> Result<()> {
+    let path = canonicalize(&path)
+        .with_context(|| format!("Failed to canonicalize path: {:?}", path.as_ref()))?;
+    let subtree = path.is_dir();
+    let mut grants = GRANTS.lock().unwrap();
+    let now = Instant::now();
+    grants.retain(|_, grant| grant.expiry > now);
+    info!("\u{25CF} Granted temporary access to {:?}.", path);
+    grants.insert(
+        path,
+        Grant {
+            expiry: now + GRANT_LIFETIME,
+            subtree,
+        },
+    );
+    Ok(())
+}
+
+fn is_granted(path: &Path) -> bool {
+    let now = Instant::now();
+    GRANTS.lock().unwrap().iter().any(|(granted, grant)| {
+        grant.expiry > now && (path == granted || grant.subtree && path.starts_with(granted))
+    })
+}
+
+fn is_under_shared_root(path: &Path) -> bool {
+    Config::get()
+        .shared_roots
+        .iter()
+        .filter_map(|root| canonicalize(root).ok())
+        .any(|root| path.starts_with(root))
+}
+
+pub fn resolve_shared_path<P: AsRef<Path>>(path: P) -> Option<PathBuf> {
+    let path = canonicalize(path).ok()?;
+    (is_under_shared_root(&path) || is_granted(&path)).then_some(path)
+}
@@ -0,0 +1,107 @@
+use rouille::Response;
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    InvalidRequest,
+    Unauthorized,
+    PathNotShared,
+    NotFound,
+    UploadIncomplete,
+    Cancelled,
+    PayloadTooLarge,
+    RangeNotSatisfiable,
+    HashMismatch,
+    Internal,
+    InsufficientStorage,
+    QuotaExceeded,
+}
+
+impl ErrorCode {
+    pub fn status(self) -> u16 {
+        match self {
+            Self::InvalidRequest => 400,
+            Self::Unauthorized => 401,
+            Self::PathNotShared => 403,
+            Self::NotFound => 404,
+            Self::UploadIncomplete | Self::Cancelled => 409,
+            Self::PayloadTooLarge => 413,
+            Self::RangeNotSatisfiable => 416,
+            Self::HashMismatch => 422,
+            Self::Internal => 500,
+            Self::InsufficientStorage | Self::QuotaExceeded => 507,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    msg: String,
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, msg: impl Into<String>) -> Self {
+        Self {
+            code,
+            msg: msg.into(),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+#[derive(Serialize)]
+pub struct ApiResponse<T: Serialize> {
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<ErrorCode>,
+    pub msg: String,
+    pub data: Option<T>,
+}
+
+pub fn ok<T: Serialize>(msg: impl Into<String>, data: T) -> Response {
+    Response::json(&ApiResponse {
+        success: true,
+        code: None,
+        msg: msg.into(),
+        data: Some(data),
+    })
+}
+
+pub fn ok_empty(msg: impl Into<String>) -> Response {
+    Response::json(&ApiResponse::<()> {
+        success: true,
+        code: None,
+        msg: msg.into(),
+        data: None,
+    })
+}
+
+pub fn error_with_data<T: Serialize>(code: ErrorCode, msg: impl Into<String>, data: T) -> Response {
+    Response::json(&ApiResponse {
+        success: false,
+        code: Some(code),
+        msg: msg.into(),
+        data: Some(data),
+    })
+    .with_status_code(code.status())
+}
+
+pub fn error(code: ErrorCode, msg: impl Into<String>) -> Response {
+    Response::json(&ApiResponse::<()> {
+        success: false,
+        code: Some(code),
+        msg: msg.into(),
+        data: None,
+    })
+    .with_status_code(code.status())
+}
@@ -0,0 +1,348 @@
+use anyhow::{Context, Result};
+use chrono::{Datelike, Local, Timelike};
+use log::{info, warn};
+use std::collections::HashSet;
+use std::fs::{File, Metadata, metadata, read_dir, symlink_metadata};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread;
+use std::time::SystemTime;
+use zip::write::{SimpleFileOptions, StreamWriter};
+use zip::{CompressionMethod, DateTime, ZipWriter};
+
+// The archive is handed to the response in chunks of this size, a few at a time.
+const CHUNK_SIZE: usize = 64 * 1024;
+const CHUNK_BACKLOG: usize = 4;
+
+type ZipStream<W> = ZipWriter<StreamWriter<W>>;
+
+// Zip entries carry no time zone and are shown as local time by unzip tools.
+fn zip_date_time(modified: SystemTime) -> DateTime {
+    let modified: chrono::DateTime<Local> = modified.into();
+    DateTime::from_date_and_time(
+        modified.year().clamp(1980, 2107) as u16,
+        modified.month() as u8,
+        modified.day() as u8,
+        modified.hour() as u8,
+        modified.minute() as u8,
+        modified.second() as u8,
+    )
+    .unwrap_or_default()
+}
+
+fn is_compressed_format(path: &Path) -> bool {
+    let mime_type = mime_guess::from_path(path).first_or_octet_stream();
+    matches!(mime_type.type_().as_str(), "image" | "video" | "audio")
+        || matches!(
+            mime_type.subtype().as_str(),
+            "zip" | "gzip" | "x-7z-compressed" | "vnd.rar" | "x-rar-compressed"
+        )
+}
+
+fn entry_options(metadata: &Metadata) -> SimpleFileOptions {
+    match metadata.modified() {
+        Ok(modified) => SimpleFileOptions::default().last_modified_time(zip_date_time(modified)),
+        Err(_) => SimpleFileOptions::default(),
+    }
+}
+
+fn add_file<W: Write>(zip: &mut ZipStream<W>, path: &Path, entry_name: &str) -> Result<()> {
+    // Opened before its entry is started, so an unreadable file is left out instead of
+    // breaking off the archive halfway.
+    let opened = File::open(path).and_then(|file| Ok((file.metadata()?, file)));
+    let (metadata, mut file) = match opened {
+        Ok(opened) => opened,
+        Err(error) => {
+            warn!("Skipped unreadable file in archive {:?}: {}", path, error);
+            return Ok(());
+        }
+    };
+    let options = entry_options(&metadata)
+        .large_file(metadata.len() >= u32::MAX as u64)
+        .compression_method(if is_compressed_format(path) {
+            CompressionMethod::Stored
+        } else {
+            CompressionMethod::Deflated
+        });
+
+    zip.start_file(entry_name, options)
+        .with_context(|| format!("Failed to start zip entry: {:?}", entry_name))?;
+    io::copy(&mut file, zip).with_context(|| format!("Failed to compress file: {:?}", path))?;
+    Ok(())
+}
+
+fn add_directory<W: Write>(zip: &mut ZipStream<W>, path: &Path, entry_name: &str) -> Result<()> {
+    let listed = metadata(path).and_then(|metadata| {
+        let children = read_dir(path)?.collect::<io::Result<Vec<_>>>()?;
+        Ok((metadata, children))
+    });
+    let (metadata, mut children) = match listed {
+        Ok(listed) => listed,
+        Err(error) => {
+            warn!(
+                "Skipped unreadable directory in archive {:?}: {}",
+                path, error
+            );
+            return Ok(());
+        }
+    };
+    zip.add_directory(entry_name, entry_options(&metadata))
+        .with_context(|| format!("Failed to add zip directory: {:?}", entry_name))?;
+    children.sort_by_key(|child| child.file_name());
+
+    for child in children {
+        let child_path = child.path();
+        let child_name = format!("{}/{}", entry_name, child.file_name().to_string_lossy());
+        let metadata = match symlink_metadata(&child_path) {
+            Ok(metadata) => metadata,
+            Err(error) => {
+                warn!(
+                    "Skipped unreadable entry in archive {:?}: {}",
+                    child_path, error
+                );
+                continue;
+            }
+        };
+        if metadata.is_symlink() {
+            info!(
+                "\u{25CF} Skipped symbolic link in archive: {:?}.",
+                child_path
+            );
+        } else if metadata.is_dir() {
+            add_directory(zip, &child_path, &child_name)?;
+        } else if metadata.is_file() {
+            add_file(zip, &child_path, &child_name)?;
+        }
+    }
+    Ok(())
+}
+
+fn unique_entry_name(used_names: &mut HashSet<String>, path: &Path) -> String {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "root".to_owned());
+    let mut unique_name = name.clone();
+    for i in 1.. {
+        if used_names.insert(unique_name.clone()) {
+            break;
+        }
+        unique_name = format!("{} ({})", name, i);
+    }
+    unique_name
+}
+
+fn write_archive<W: Write>(writer: W, paths: &[PathBuf]) -> Result<()> {
+    let mut zip = ZipWriter::new_stream(writer);
+    let mut used_names = HashSet::new();
+    for path in paths {
+        let entry_name = unique_entry_name(&mut used_names, path);
+        if path.is_dir() {
+            add_directory(&mut zip, path, &entry_name)?;
+        } else {
+            add_file(&mut zip, path, &entry_name)?;
+        }
+    }
+    zip.finish().context("Failed to finish zip archive")?;
+    Ok(())
+}
+
+pub fn archive_name(paths: &[PathBuf]) -> String {
+    let name = match paths {
+        [path] => path.file_name(),
+        [first, rest @ ..] => first
+            .parent()
+            .filter(|parent| rest.iter().all(|path| path.parent() == Some(*parent)))
+            .and_then(Path::file_name),
+        [] => None,
+    };
+    format!(
+        "{}.zip",
+        name.map_or_else(|| "files".into(), |name| name.to_string_lossy())
+    )
+}
+
+// Carries the archive from the writer thread; an error ends the stream as an `Err`
+// rather than a silently truncated zip.
+pub struct ArchiveReader {
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    position: usize,
+    finished: bool,
+}
+
+impl Read for ArchiveReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.chunk.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            match self.chunks.recv() {
+                Ok(Ok(chunk)) if chunk.is_empty() => self.finished = true,
+                Ok(Ok(chunk)) => {
+                    self.chunk = chunk;
+                    self.position = 0;
+                }
+                Ok(Err(error)) => return Err(error),
+                Err(_) => {
+                    return Err(io::Error::other(
+                        "The zip archive stream ended unexpectedly",
+                    ));
+                }
+            }
+        }
+        let read = buf.len().min(self.chunk.len() - self.position);
+        buf[..read].copy_from_slice(&self.chunk[self.position..self.position + read]);
+        self.position += read;
+        Ok(read)
+    }
+}
+
+struct ChunkWriter {
+    chunks: SyncSender<io::Result<Vec<u8>>>,
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.chunks
+            .send(Ok(buf.to_vec()))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "The download was closed"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub fn stream_zip(paths: Vec<PathBuf>) -> ArchiveReader {
+    let (sender, receiver) = mpsc::sync_channel(CHUNK_BACKLOG);
+    thread::spawn(move || {
+        let mut writer = BufWriter::with_capacity(
+            CHUNK_SIZE,
+            ChunkWriter {
+                chunks: sender.clone(),
+            },
+        );
+        let result = write_archive(&mut writer, &paths)
+            .and_then(|()| writer.flush().context("Failed to finish zip archive"));
+        drop(writer);
+        match &result {
+            Ok(()) => info!("\u{25CF} Finished streaming zip archive of {:?}.", paths),
+            Err(error) => warn!("Failed to stream zip archive of {:?}: {:?}", paths, error),
+        }
+        // An empty chunk marks the end, anything else after a failure is an error.
+        sender
+            .send(
+                result
+                    .map(|()| Vec::new())
+                    .map_err(|error| io::Error::other(format!("{:#}", error))),
+            )
+            .ok();
+    });
+    ArchiveReader {
+        chunks: receiver,
+        chunk: Vec::new(),
+        position: 0,
+        finished: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+    use std::io::Cursor;
+    use std::process;
+    use zip::ZipArchive;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("airdroppro-{}-{}", name, process::id()));
+        remove_dir_all(&dir).ok();
+        dir
+    }
+
+    fn read_entry(archive: &mut ZipArchive<Cursor<Vec<u8>>>, name: &str) -> String {
+        let mut contents = String::new();
+        archive
+            .by_name(name)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        contents
+    }
+
+    #[test]
+    fn streams_directory_archive() {
+        let dir = temp_dir("archive");
+        create_dir_all(dir.join("photos/nested")).unwrap();
+        write(dir.join("photos/notes.txt"), "hello").unwrap();
+        write(dir.join("photos/nested/image.jpg"), vec![7u8; 200_000]).unwrap();
+
+        let mut bytes = Vec::new();
+        stream_zip(vec![dir.join("photos")])
+            .read_to_end(&mut bytes)
+            .unwrap();
+        let mut archive = ZipArchive::new(Cursor::new(bytes)).unwrap();
+
+        let mut names: Vec<_> = archive
+            .file_names()
+            .map(|name| name.unwrap().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "photos/",
+                "photos/nested/",
+                "photos/nested/image.jpg",
+                "photos/notes.txt"
+            ]
+        );
+        assert_eq!(read_entry(&mut archive, "photos/notes.txt"), "hello");
+        assert_eq!(
+            archive.by_name("photos/nested/image.jpg").unwrap().size(),
+            200_000
+        );
+        remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn skips_missing_entries() {
+        let dir = temp_dir("archive-missing");
+        create_dir_all(&dir).unwrap();
+        write(dir.join("kept.txt"), "kept").unwrap();
+
+        let mut bytes = Vec::new();
+        stream_zip(vec![dir.join("kept.txt"), dir.join("gone.txt")])
+            .read_to_end(&mut bytes)
+            .unwrap();
+        let mut archive = ZipArchive::new(Cursor::new(bytes)).unwrap();
+
+        assert_eq!(archive.len(), 1);
+        assert_eq!(read_entry(&mut archive, "kept.txt"), "kept");
+        remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn reports_failed_archive_as_error() {
+        let (sender, receiver) = mpsc::sync_channel(CHUNK_BACKLOG);
+        sender.send(Ok(b"PK".to_vec())).unwrap();
+        sender.send(Err(io::Error::other("disk vanished"))).unwrap();
+        let mut reader = ArchiveReader {
+            chunks: receiver,
+            chunk: Vec::new(),
+            position: 0,
+            finished: false,
+        };
+
+        let mut bytes = Vec::new();
+        assert!(reader.read_to_end(&mut bytes).is_err());
+        assert_eq!(bytes, b"PK");
+    }
+}
@@ -0,0 +1,229 @@
+use crate::api::{ApiError, ErrorCode};
+use crate::config::Config;
+use crate::tray;
+use crate::utils::{decode_image, encode_image_to_png, get_config_dir};
+use anyhow::{Context, Result};
+use arboard::{Clipboard, ImageData};
+use log::{info, warn};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{create_dir_all, read, read_to_string, remove_file, write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const HISTORY_FILE_NAME: &str = "clipboard_history.json";
+const IMAGE_DIR_NAME: &str = "clipboard_history";
+
+static HISTORY: Mutex<Option<Vec<HistoryEntry>>> = Mutex::new(None);
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum HistoryContent {
+    #[serde(rename = "text")]
+    Text(String),
+    #[serde(rename = "img")]
+    Image {
+        width: usize,
+        height: usize,
+        hash: String,
+    },
+    #[serde(rename = "file")]
+    Files(Vec<String>),
+    #[serde(rename = "html")]
+    Html(String),
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: u64,
+    pub timestamp: u64,
+    pub content: HistoryContent,
+}
+
+impl HistoryEntry {
+    pub fn preview(&self) -> String {
+        const PREVIEW_LENGTH: usize = 40;
+        let preview = match &self.content {
+            HistoryContent::Text(text) => text.split_whitespace().collect::<Vec<_>>().join(" "),
+            HistoryContent::Image { width, height, .. } => {
+                format!("Image {}\u{00D7}{}", width, height)
+            }
+            HistoryContent::Files(paths) => paths
+                .iter()
+                .map(|path| {
+                    PathBuf::from(path)
+                        .file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_else(|| path.clone())
+                })
+                .collect::<Vec<_>>()
+                .join(", "),
+            HistoryContent::Html(html) => Regex::new("<[^>]*>")
+                .map(|tags| tags.replace_all(html, " ").into_owned())
+                .unwrap_or_else(|_| html.clone())
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" "),
+        };
+        if preview.chars().count() > PREVIEW_LENGTH {
+            let truncated: String = preview.chars().take(PREVIEW_LENGTH).collect();
+            format!("{}\u{2026}", truncated)
+        } else {
+            preview
+        }
+    }
+
+    fn image_path(&self) -> Result<PathBuf> {
+        Ok(get_config_dir()?
+            .join(IMAGE_DIR_NAME)
+            .join(format!("{}.png", self.id)))
+    }
+}
+
+fn history_path() -> Result<PathBuf> {
+    Ok(get_config_dir()?.join(HISTORY_FILE_NAME))
+}
+
+fn load() -> Vec<HistoryEntry> {
+    let loaded = history_path().and_then(|path| {
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let json = read_to_string(&path)
+            .with_context(|| format!("Failed to read clipboard history from {:?}", path))?;
+        serde_json::from_str(&json).context("Failed to parse clipboard history")
+    });
+    loaded.unwrap_or_else(|error| {
+        warn!("Failed to load clipboard history: {:?}", error);
+        Vec::new()
+    })
+}
+
+fn save(entries: &[HistoryEntry]) -> Result<()> {
+    let path = history_path()?;
+    let json = serde_json::to_string(entries).context("Failed to serialize clipboard history")?;
+    write(&path, json).with_context(|| format!("Failed to write clipboard history to {:?}", path))
+}
+
+fn with_history<T>(action: impl FnOnce(&mut Vec<HistoryEntry>) -> T) -> T {
+    let mut history = HISTORY.lock().unwrap();
+    action(history.get_or_insert_with(load))
+}
+
+fn record(content: HistoryContent, image: Option<&ImageData>) -> Result<()> {
+    let capacity = Config::get().clipboard_history_size;
+    if capacity == 0 {
+        return Ok(());
+    }
+
+    let recorded = with_history(|entries| -> Result<bool> {
+        if entries
+            .first()
+            .is_some_and(|entry| entry.content == content)
+        {
+            return Ok(false);
+        }
+
+        let entry = HistoryEntry {
+            id: entries.first().map_or(1, |entry| entry.id + 1),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            content,
+        };
+        if let Some(image) = image {
+            let image_path = entry.image_path()?;
+            if let Some(image_dir) = image_path.parent() {
+                create_dir_all(image_dir)?;
+            }
+            write(&image_path, encode_image_to_png(image)?)
+                .with_context(|| format!("Failed to store clipboard image {:?}", image_path))?;
+        }
+        entries.insert(0, entry);
+
+        for evicted in entries.drain(capacity.min(entries.len())..) {
+            if matches!(evicted.content, HistoryContent::Image { .. })
+                && let Ok(image_path) = evicted.image_path()
+            {
+                remove_file(image_path).ok();
+            }
+        }
+        save(entries)?;
+        Ok(true)
+    })?;
+
+    if recorded {
+        tray::refresh_menu();
+    }
+    Ok(())
+}
+
+pub fn record_text(text: &str) -> Result<()> {
+    record(HistoryContent::Text(text.to_owned()), None)
+}
+
+pub fn record_image(image: &ImageData) -> Result<()> {
+    let hash = format!("{:x}", Sha256::digest(image.bytes.as_ref()));
+    let content = HistoryContent::Image {
+        width: image.width,
+        height: image.height,
+        hash,
+    };
+    record(content, Some(image))
+}
+
+pub fn record_files(paths: &[String]) -> Result<()> {
+    record(HistoryContent::Files(paths.to_vec()), None)
+}
+
+pub fn record_html(html: &str) -> Result<()> {
+    record(HistoryContent::Html(html.to_owned()), None)
+}
+
+pub fn entries() -> Vec<HistoryEntry> {
+    with_history(|entries| entries.clone())
+}
+
+pub fn restore(id: u64) -> Result<HistoryEntry> {
+    let entry = with_history(|entries| entries.iter().find(|entry| entry.id == id).cloned())
+        .ok_or_else(|| {
+            ApiError::new(
+                ErrorCode::NotFound,
+                format!("Unknown clipboard history entry: {}", id),
+            )
+        })?;
+
+    let mut clipboard = Clipboard::new().context("Failed to initialize clipboard")?;
+    match &entry.content {
+        HistoryContent::Text(text) => clipboard
+            .set_text(text)
+            .context("Failed to set clipboard text")?,
+        HistoryContent::Image { .. } => {
+            let image_path = entry.image_path()?;
+            let bytes = read(&image_path)
+                .with_context(|| format!("Failed to read clipboard image {:?}", image_path))?;
+            clipboard
+                .set_image(decode_image(&bytes)?)
+                .context("Failed to set clipboard image")?
+        }
+        HistoryContent::Files(paths) => clipboard
+            .set()
+            .file_list(paths)
+            .context("Failed to set clipboard file list")?,
+        HistoryContent::Html(html) => clipboard
+            .set_html(html.as_str(), None)
+            .context("Failed to set clipboard HTML")?,
+    }
+    thread::sleep(Duration::from_millis(200));
+
+    info!(
+        "\u{25CF} Restored clipboard history entry {}: {}.",
+        entry.id,
+        entry.preview()
+    );
+    Ok(entry)
+}
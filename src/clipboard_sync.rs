@@ -0,0 +1,150 @@
+use crate::config::Config;
+use arboard::Clipboard;
+use log::{info, warn};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+// Local reads this soon after a push are taken as the push itself settling on the clipboard.
+const ABSORB_WINDOW: Duration = Duration::from_secs(3);
+
+static STATE: Mutex<SyncState> = Mutex::new(SyncState {
+    sequence: 0,
+    hash: String::new(),
+    kind: "",
+    absorb_until: None,
+});
+static CHANGED: Condvar = Condvar::new();
+
+struct SyncState {
+    sequence: u64,
+    hash: String,
+    kind: &'static str,
+    absorb_until: Option<Instant>,
+}
+
+#[derive(Serialize)]
+pub struct ClipboardChange {
+    pub sequence: u64,
+    pub hash: String,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+}
+
+pub fn hash_content(kind: &str, bytes: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(kind.as_bytes());
+    hasher.update([0]);
+    hasher.update(bytes);
+    format!("{:x}", hasher.finalize())
+}
+
+fn read_clipboard(clipboard: &mut Clipboard) -> Option<(&'static str, String)> {
+    if let Ok(image) = clipboard.get().image() {
+        return Some(("img", hash_content("img", &image.bytes)));
+    }
+    if let Ok(file_list) = clipboard.get().file_list()
+        && !file_list.is_empty()
+    {
+        let paths: Vec<_> = file_list
+            .iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect();
+        return Some(("file", hash_content("file", paths.join("\n").as_bytes())));
+    }
+    if let Ok(text) = clipboard.get().text() {
+        return Some(("text", hash_content("text", text.as_bytes())));
+    }
+    None
+}
+
+fn observe(kind: &'static str, hash: String) {
+    let mut state = STATE.lock().unwrap();
+    if state.hash == hash {
+        return;
+    }
+    if state
+        .absorb_until
+        .is_some_and(|absorb_until| Instant::now() < absorb_until)
+    {
+        // The platform may alter content pushed by a client (e.g. re-encoding images or
+        // exposing HTML as text), and the previous content may still be read before the
+        // push lands, so neither is announced back as a local change.
+        state.hash = hash;
+        return;
+    }
+    state.absorb_until = None;
+    state.sequence += 1;
+    state.hash = hash;
+    state.kind = kind;
+    info!(
+        "\u{25CF} Clipboard changed locally, sync sequence {}.",
+        state.sequence
+    );
+    CHANGED.notify_all();
+}
+
+pub fn is_enabled() -> bool {
+    Config::get().clipboard_sync
+}
+
+pub fn is_current(hash: &str) -> bool {
+    is_enabled() && STATE.lock().unwrap().hash == hash
+}
+
+// Called before the content is put on the clipboard, so the watcher never sees it unannounced.
+pub fn record_remote(kind: &'static str, hash: String) {
+    if !is_enabled() {
+        return;
+    }
+    let mut state = STATE.lock().unwrap();
+    if state.hash == hash {
+        return;
+    }
+    state.sequence += 1;
+    state.hash = hash;
+    state.kind = kind;
+    state.absorb_until = Some(Instant::now() + ABSORB_WINDOW);
+    CHANGED.notify_all();
+}
+
+// A `since` ahead of the sequence comes from before a restart and is answered right away.
+pub fn wait_for_change(since: u64, timeout: Duration) -> Option<ClipboardChange> {
+    let state = STATE.lock().unwrap();
+    let (state, _) = CHANGED
+        .wait_timeout_while(state, timeout, |state| state.sequence == since)
+        .unwrap();
+    (state.sequence > 0 || since > state.sequence).then(|| ClipboardChange {
+        sequence: state.sequence,
+        hash: state.hash.clone(),
+        kind: state.kind,
+    })
+}
+
+pub fn spawn_watcher() {
+    thread::spawn(|| {
+        let mut clipboard = None;
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            if !is_enabled() {
+                clipboard = None;
+                continue;
+            }
+            if clipboard.is_none() {
+                match Clipboard::new() {
+                    Ok(new_clipboard) => clipboard = Some(new_clipboard),
+                    Err(error) => {
+                        warn!("Failed to open clipboard for sync: {:?}", error);
+                        continue;
+                    }
+                }
+            }
+            if let Some((kind, hash)) = clipboard.as_mut().and_then(read_clipboard) {
+                observe(kind, hash);
+            }
+        }
+    });
+}
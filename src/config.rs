@@ -1,17 +1,294 @@
-use crate::utils::{get_config_path, resolve_base_directory, set_auto_startup};
+use crate::mdns;
+use crate::routing::RoutingRule;
+use crate::server;
+use crate::tls;
+use crate::utils::{get_config_path, resolve_base_directory, set_auto_startup, show_notification};
 use anyhow::{Context, Result, ensure};
 use configparser::ini::Ini;
-use log::info;
-use std::fs::write;
-use std::path::PathBuf;
-use std::sync::OnceLock;
+use log::{error, info};
+use native_dialog::{DialogBuilder, MessageLevel};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{File, copy, create_dir_all, metadata, read_to_string, remove_file, write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
 
-static CONFIG: OnceLock<Config> = OnceLock::new();
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+static CONFIG: RwLock<Option<Arc<Config>>> = RwLock::new(None);
 
 pub struct Config {
     pub name: String,
     pub port: u16,
+    pub https_port: Option<u16>,
+    pub key: String,
+    pub allow_anonymous: bool,
     pub path: PathBuf,
+    pub shared_roots: Vec<PathBuf>,
+    pub rename_template: String,
+    pub on_conflict: ConflictPolicy,
+    pub on_upload_error: UploadErrorPolicy,
+    pub routing_rules: Vec<RoutingRule>,
+    pub upload_session_timeout: Duration,
+    pub max_file_size: u64,
+    pub max_request_size: u64,
+    pub min_free_space: u64,
+    pub upload_quota: u64,
+    pub upload_quota_window: Duration,
+    pub transfer_history_size: usize,
+    pub clipboard_history_size: usize,
+    pub clipboard_sync: bool,
+    pub clipboard_priority: Vec<ClipboardKind>,
+    pub clipboard_image_max_size: u32,
+    pub clipboard_image_quality: u8,
+    pub clipboard_image_inline_limit: usize,
+    pub auto_launch: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClipboardKind {
+    Image,
+    Files,
+    HtmlFiles,
+    Text,
+}
+
+impl ClipboardKind {
+    pub const ALL: [Self; 4] = [Self::Image, Self::Files, Self::HtmlFiles, Self::Text];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "img" => Some(Self::Image),
+            "file" => Some(Self::Files),
+            "html_links" => Some(Self::HtmlFiles),
+            "text" => Some(Self::Text),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    Rename,
+    Overwrite,
+    Skip,
+    Dedupe,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UploadErrorPolicy {
+    Keep,
+    Rollback,
+}
+
+const DEFAULT_CONFIG_BYTES: &[u8] = include_bytes!("../config.ini");
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "true" | "t" | "yes" | "y" | "1" | "on" => Some(true),
+        "false" | "f" | "no" | "n" | "0" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let multiplier: u64 = match unit.trim().to_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1 << 10,
+        "m" | "mb" => 1 << 20,
+        "g" | "gb" => 1 << 30,
+        "t" | "tb" => 1 << 40,
+        _ => return None,
+    };
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+fn parse_list(value: &str) -> Vec<&str> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+fn is_valid_host_name(name: &str) -> bool {
+    let name = name.trim_end_matches('.');
+    let name = name.strip_suffix(".local").unwrap_or(name);
+    !name.is_empty()
+        && name.len() <= 253
+        && name.split('.').all(|label| {
+            (1..=63).contains(&label.len())
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+        })
+}
+
+fn check_writable(path: &Path) -> Result<()> {
+    create_dir_all(path).with_context(|| format!("cannot create {:?}", path))?;
+    let probe_path = path.join(".airdroppro-write-test");
+    File::create(&probe_path).with_context(|| format!("cannot write into {:?}", path))?;
+    remove_file(&probe_path).ok();
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct ConfigError {
+    pub path: PathBuf,
+    pub problems: Vec<String>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Found {} problem(s) in {:?}:",
+            self.problems.len(),
+            self.path
+        )?;
+        for problem in &self.problems {
+            write!(f, "\n\u{2022} {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+struct ConfigReader {
+    ini: Ini,
+    lines: HashMap<(String, String), usize>,
+    problems: Vec<String>,
+}
+
+impl ConfigReader {
+    fn new(text: &str) -> Result<Self> {
+        let mut ini = Ini::new();
+        ini.read(text.to_owned())
+            .map_err(|error| anyhow::anyhow!(error))?;
+
+        let mut lines = HashMap::new();
+        let mut section = String::new();
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if let Some(name) = line
+                .strip_prefix('[')
+                .and_then(|line| line.strip_suffix(']'))
+            {
+                section = name.trim().to_lowercase();
+            } else if let Some((key, _)) = line.split_once(['=', ':']) {
+                lines.insert((section.clone(), key.trim().to_lowercase()), index + 1);
+            }
+        }
+
+        Ok(Self {
+            ini,
+            lines,
+            problems: Vec::new(),
+        })
+    }
+
+    fn section_entries(&self, section: &str) -> Vec<(String, String)> {
+        let section = section.to_lowercase();
+        let mut entries: Vec<_> = self
+            .ini
+            .get_map_ref()
+            .get(&section)
+            .into_iter()
+            .flatten()
+            .map(|(key, value)| (key.clone(), value.clone().unwrap_or_default()))
+            .collect();
+        entries.sort_by_key(|(key, _)| self.lines.get(&(section.clone(), key.clone())).copied());
+        entries
+    }
+
+    fn get(&self, section: &str, key: &str) -> Option<String> {
+        self.ini.get(section, key)
+    }
+
+    fn require(&mut self, section: &str, key: &str) -> Option<String> {
+        let value = self.get(section, key);
+        if value.is_none() {
+            self.problems
+                .push(format!("missing '{}' key in [{}] section", key, section));
+        }
+        value
+    }
+
+    fn report(&mut self, section: &str, key: &str, problem: String) {
+        let line = self
+            .lines
+            .get(&(section.to_lowercase(), key.to_lowercase()));
+        self.problems.push(match line {
+            Some(line) => format!("line {}: {}", line, problem),
+            None => problem,
+        });
+    }
+
+    fn parse_port(&mut self, section: &str, key: &str, value: &str) -> Option<u16> {
+        match value.parse::<u16>() {
+            Ok(port) if port != 0 => Some(port),
+            _ => {
+                self.report(
+                    section,
+                    key,
+                    format!(
+                        "'{}' value '{}' is not a number between 1 and 65535",
+                        key, value
+                    ),
+                );
+                None
+            }
+        }
+    }
+
+    fn parse_size(&mut self, section: &str, key: &str, value: &str) -> Option<u64> {
+        let parsed = parse_size(value);
+        if parsed.is_none() {
+            self.report(
+                section,
+                key,
+                format!(
+                    "'{}' value '{}' is not a size such as 1048576, 512K, 100M or 2G",
+                    key, value
+                ),
+            );
+        }
+        parsed
+    }
+
+    fn parse_bool(&mut self, section: &str, key: &str, value: &str) -> Option<bool> {
+        let parsed = parse_bool(&value.to_lowercase());
+        if parsed.is_none() {
+            self.report(
+                section,
+                key,
+                format!(
+                    "'{}' value '{}' is not one of true/false, yes/no, on/off or 1/0",
+                    key, value
+                ),
+            );
+        }
+        parsed
+    }
+}
+
+pub fn write_default_config(config_path: &Path) -> Result<()> {
+    let default_config_str = str::from_utf8(DEFAULT_CONFIG_BYTES)
+        .context("Failed to decode default config string with UTF-8")?;
+    write(config_path, default_config_str).with_context(|| {
+        format!(
+            "Failed to write the default config into path {:?}",
+            config_path
+        )
+    })
 }
 
 impl Config {
@@ -23,71 +300,493 @@ impl Config {
         );
         if !config_path.exists() {
             info!("Failed to: {:?}", config_path);
-            const DEFAULT_CONFIG_BYTES: &[u8] = include_bytes!("../config.ini");
-            let default_config_str = str::from_utf8(DEFAULT_CONFIG_BYTES)
-                .context("Failed to decode default config string with UTF-8")?;
-            write(&config_path, default_config_str).with_context(|| {
-                format!(
-                    "Failed to write the default config into path {:?}",
-                    config_path
-                )
-            })?;
+            write_default_config(&config_path)?;
         }
 
-        let mut ini = Ini::new();
-        ini.load(&config_path)
-            .map_err(|error| anyhow::anyhow!(error))
+        let text = read_to_string(&config_path)
+            .with_context(|| format!("Failed to read config file from {:?}", config_path))?;
+        let mut reader = ConfigReader::new(&text)
             .with_context(|| format!("Failed to load config file from {:?}", config_path))?;
 
-        let name = ini
-            .get("Server", "name")
-            .context("Config missing 'name' key in [Server] section")?;
-
-        let port = ini
-            .get("Server", "port")
-            .context("Config missing 'port' key in [Server] section")?;
-        let port = port.parse::<u16>().with_context(|| {
-            format!(
-                "Failed to parse 'port' value '{}' as a number between 0 and 65535",
-                port
-            )
-        })?;
-
-        let path = ini
-            .get("Application", "download path")
-            .context("Config missing 'path' key in [Application] section")?;
-        let path = resolve_base_directory(&path).context("Failed to generate download path")?;
-
-        let auto_launch = ini
-            .get("Application", "auto launch")
-            .context("Config missing 'auto launch' key in [Application] section")?;
-        let auto_launch = match auto_launch.as_str() {
-            "true" | "t" | "yes" | "y" | "1" | "on" => Some(true),
-            "false" | "f" | "no" | "n" | "0" | "off" => Some(false),
-            _ => None,
+        let name = reader.require("Server", "name").unwrap_or_default();
+        if !is_valid_host_name(&name) {
+            reader.report(
+                "Server",
+                "name",
+                format!(
+                    "'name' value '{}' is not a valid host name (letters, digits and '-' only, at most 63 per label)",
+                    name
+                ),
+            );
+        }
+
+        let port = reader
+            .require("Server", "port")
+            .and_then(|port| reader.parse_port("Server", "port", &port))
+            .unwrap_or_default();
+
+        let https_port = reader
+            .get("Server", "https port")
+            .filter(|https_port| !https_port.is_empty())
+            .and_then(|https_port| reader.parse_port("Server", "https port", &https_port));
+        if https_port.is_some_and(|https_port| https_port == port) {
+            reader.report(
+                "Server",
+                "https port",
+                "'https port' must differ from 'port'".to_owned(),
+            );
+        }
+
+        let key = reader.require("Server", "key").unwrap_or_default();
+
+        let allow_anonymous = reader
+            .get("Server", "allow anonymous")
+            .and_then(|value| reader.parse_bool("Server", "allow anonymous", &value))
+            .unwrap_or(false);
+        if !allow_anonymous && key.is_empty() {
+            reader.report(
+                "Server",
+                "key",
+                "'key' is empty while 'allow anonymous' is off".to_owned(),
+            );
+        }
+
+        let path = reader
+            .require("Application", "download path")
+            .unwrap_or_default();
+        let path = match resolve_base_directory(&path) {
+            Ok(path) => {
+                if let Err(error) = check_writable(&path) {
+                    reader.report(
+                        "Application",
+                        "download path",
+                        format!("'download path' is not writable: {:#}", error),
+                    );
+                }
+                path
+            }
+            Err(error) => {
+                reader.report(
+                    "Application",
+                    "download path",
+                    format!("'download path' cannot be resolved: {:#}", error),
+                );
+                PathBuf::new()
+            }
+        };
+
+        let mut shared_roots = vec![path.clone()];
+        let roots = reader
+            .get("Application", "shared roots")
+            .unwrap_or_default();
+        for root in parse_list(&roots) {
+            match resolve_base_directory(root) {
+                Ok(root) => shared_roots.push(root),
+                Err(error) => reader.report(
+                    "Application",
+                    "shared roots",
+                    format!("shared root '{}' cannot be resolved: {:#}", root, error),
+                ),
+            }
+        }
+
+        let rename_template = reader
+            .get("Application", "rename template")
+            .unwrap_or_else(|| "{name}({n}){ext}".to_owned());
+        if !rename_template.contains("{n}") {
+            reader.report(
+                "Application",
+                "rename template",
+                format!(
+                    "'rename template' value '{}' must contain the {{n}} counter",
+                    rename_template
+                ),
+            );
+        } else if rename_template.contains(['/', '\\']) {
+            reader.report(
+                "Application",
+                "rename template",
+                format!(
+                    "'rename template' value '{}' must not contain path separators",
+                    rename_template
+                ),
+            );
+        }
+
+        let on_conflict = match reader.get("Application", "on conflict") {
+            Some(policy) => match policy.to_lowercase().as_str() {
+                "rename" => ConflictPolicy::Rename,
+                "overwrite" => ConflictPolicy::Overwrite,
+                "skip" => ConflictPolicy::Skip,
+                "dedupe" => ConflictPolicy::Dedupe,
+                _ => {
+                    reader.report(
+                        "Application",
+                        "on conflict",
+                        format!(
+                            "'on conflict' value '{}' is not one of rename, overwrite, skip or dedupe",
+                            policy
+                        ),
+                    );
+                    ConflictPolicy::Rename
+                }
+            },
+            None => ConflictPolicy::Rename,
+        };
+
+        let on_upload_error = match reader.get("Application", "on upload error") {
+            Some(policy) => match policy.to_lowercase().as_str() {
+                "keep" => UploadErrorPolicy::Keep,
+                "rollback" => UploadErrorPolicy::Rollback,
+                _ => {
+                    reader.report(
+                        "Application",
+                        "on upload error",
+                        format!(
+                            "'on upload error' value '{}' is not one of keep or rollback",
+                            policy
+                        ),
+                    );
+                    UploadErrorPolicy::Keep
+                }
+            },
+            None => UploadErrorPolicy::Keep,
+        };
+        // A rollback cannot bring back a file that an upload overwrote.
+        if on_upload_error == UploadErrorPolicy::Rollback
+            && on_conflict == ConflictPolicy::Overwrite
+        {
+            reader.report(
+                "Application",
+                "on upload error",
+                "'on upload error = rollback' cannot be combined with 'on conflict = overwrite'"
+                    .to_owned(),
+            );
+        }
+
+        let mut routing_rules = Vec::new();
+        for (pattern, destination) in reader.section_entries("Routing") {
+            match RoutingRule::parse(&pattern, &destination) {
+                Ok(rule) => routing_rules.push(rule),
+                Err(problem) => reader.report("Routing", &pattern, problem),
+            }
+        }
+
+        let upload_session_timeout = match reader.get("Application", "upload session timeout") {
+            Some(timeout) => timeout.parse::<u64>().unwrap_or_else(|_| {
+                reader.report(
+                    "Application",
+                    "upload session timeout",
+                    format!(
+                        "'upload session timeout' value '{}' is not a number of seconds",
+                        timeout
+                    ),
+                );
+                0
+            }),
+            None => 24 * 60 * 60,
+        };
+        let upload_session_timeout = Duration::from_secs(upload_session_timeout);
+
+        let mut size_limit = |key: &str| {
+            reader
+                .get("Application", key)
+                .and_then(|value| reader.parse_size("Application", key, &value))
+                .unwrap_or(0)
+        };
+        let max_file_size = size_limit("max file size");
+        let max_request_size = size_limit("max request size");
+        let min_free_space = size_limit("min free space");
+        let upload_quota = size_limit("upload quota");
+
+        let upload_quota_window = match reader.get("Application", "upload quota window") {
+            Some(window) => window.parse::<u64>().unwrap_or_else(|_| {
+                reader.report(
+                    "Application",
+                    "upload quota window",
+                    format!(
+                        "'upload quota window' value '{}' is not a number of seconds",
+                        window
+                    ),
+                );
+                0
+            }),
+            None => 24 * 60 * 60,
+        };
+        let upload_quota_window = Duration::from_secs(upload_quota_window);
+
+        let clipboard_history_size = match reader.get("Application", "clipboard history") {
+            Some(size) => size.parse::<usize>().unwrap_or_else(|_| {
+                reader.report(
+                    "Application",
+                    "clipboard history",
+                    format!(
+                        "'clipboard history' value '{}' is not a number of entries",
+                        size
+                    ),
+                );
+                0
+            }),
+            None => 50,
+        };
+
+        let transfer_history_size = match reader.get("Application", "transfer history") {
+            Some(size) => size.parse::<usize>().unwrap_or_else(|_| {
+                reader.report(
+                    "Application",
+                    "transfer history",
+                    format!(
+                        "'transfer history' value '{}' is not a number of entries",
+                        size
+                    ),
+                );
+                0
+            }),
+            None => 500,
+        };
+
+        let clipboard_sync = reader
+            .get("Application", "clipboard sync")
+            .and_then(|value| reader.parse_bool("Application", "clipboard sync", &value))
+            .unwrap_or(false);
+
+        let mut clipboard_priority = Vec::new();
+        match reader.get("Application", "clipboard priority") {
+            Some(value) => {
+                for name in parse_list(&value.to_lowercase()) {
+                    match ClipboardKind::from_name(name) {
+                        Some(kind) if !clipboard_priority.contains(&kind) => {
+                            clipboard_priority.push(kind)
+                        }
+                        Some(_) => {}
+                        // `html` is the raw HTML type of POST /clipboard, not a probe.
+                        None if name == "html" => reader.report(
+                            "Application",
+                            "clipboard priority",
+                            "clipboard type 'html' was renamed to 'html_links'".to_owned(),
+                        ),
+                        None => reader.report(
+                            "Application",
+                            "clipboard priority",
+                            format!(
+                                "clipboard type '{}' is not one of img, file, html_links or text",
+                                name
+                            ),
+                        ),
+                    }
+                }
+                if parse_list(&value).is_empty() {
+                    reader.report(
+                        "Application",
+                        "clipboard priority",
+                        "'clipboard priority' must list at least one clipboard type".to_owned(),
+                    );
+                }
+            }
+            None => clipboard_priority.extend(ClipboardKind::ALL),
+        }
+
+        let clipboard_image_max_size = match reader.get("Application", "clipboard image max size") {
+            Some(size) => size.parse::<u32>().unwrap_or_else(|_| {
+                reader.report(
+                    "Application",
+                    "clipboard image max size",
+                    format!(
+                        "'clipboard image max size' value '{}' is not a number of pixels",
+                        size
+                    ),
+                );
+                0
+            }),
+            None => 0,
+        };
+
+        let clipboard_image_quality = match reader.get("Application", "clipboard image quality") {
+            Some(quality) => match quality.parse::<u8>() {
+                Ok(quality) if (1..=100).contains(&quality) => quality,
+                _ => {
+                    reader.report(
+                        "Application",
+                        "clipboard image quality",
+                        format!(
+                            "'clipboard image quality' value '{}' is not between 1 and 100",
+                            quality
+                        ),
+                    );
+                    85
+                }
+            },
+            None => 85,
+        };
+
+        let clipboard_image_inline_limit =
+            match reader.get("Application", "clipboard image inline limit") {
+                Some(limit) => limit.parse::<usize>().unwrap_or_else(|_| {
+                    reader.report(
+                        "Application",
+                        "clipboard image inline limit",
+                        format!(
+                            "'clipboard image inline limit' value '{}' is not a number of bytes",
+                            limit
+                        ),
+                    );
+                    0
+                }),
+                None => 0,
+            };
+
+        let auto_launch = reader
+            .require("Application", "auto launch")
+            .and_then(|value| reader.parse_bool("Application", "auto launch", &value))
+            .unwrap_or(false);
+
+        if !reader.problems.is_empty() {
+            return Err(ConfigError {
+                path: config_path,
+                problems: reader.problems,
+            }
+            .into());
         }
-        .context("Failed to parse 'auto launch' key")?;
-        set_auto_startup(auto_launch).context("Failed to set auto launch")?;
 
         info!("\u{2570} Configuration loaded successfully!");
-        Ok(Self { name, port, path })
+        Ok(Self {
+            name,
+            port,
+            https_port,
+            key,
+            allow_anonymous,
+            path,
+            shared_roots,
+            rename_template,
+            on_conflict,
+            on_upload_error,
+            routing_rules,
+            upload_session_timeout,
+            max_file_size,
+            max_request_size,
+            min_free_space,
+            upload_quota,
+            upload_quota_window,
+            transfer_history_size,
+            clipboard_history_size,
+            clipboard_sync,
+            clipboard_priority,
+            clipboard_image_max_size,
+            clipboard_image_quality,
+            clipboard_image_inline_limit,
+            auto_launch,
+        })
     }
 
     pub fn init() -> Result<()> {
         let config = Self::new()?;
-        CONFIG
-            .set(config)
-            .map_err(|_| anyhow::anyhow!("Config already initialized"))?;
+        set_auto_startup(config.auto_launch).context("Failed to set auto launch")?;
+        *CONFIG.write().unwrap() = Some(Arc::new(config));
 
         ensure!(
-            CONFIG.get().is_some(),
+            CONFIG.read().unwrap().is_some(),
             "Failed to ensure the CONFIG isn't None"
         );
 
         Ok(())
     }
 
-    pub fn get() -> &'static Self {
-        CONFIG.get().unwrap()
+    pub fn init_or_restore() -> Result<()> {
+        loop {
+            let Err(error) = Self::init() else {
+                return Ok(());
+            };
+            error!("Failed to load config: {:?}", error);
+
+            let restore = DialogBuilder::message()
+                .set_level(MessageLevel::Error)
+                .set_title("AirDropPro")
+                .set_text(format!(
+                    "{:#}\n\nRestore the default configuration? The current file will be kept as config.ini.bak.",
+                    error
+                ))
+                .confirm()
+                .show()
+                .context("Failed to show dialog")?;
+            if !restore {
+                return Err(error);
+            }
+
+            let config_path = get_config_path().context("Failed to get config path")?;
+            let backup_path = config_path.with_extension("ini.bak");
+            copy(&config_path, &backup_path)
+                .with_context(|| format!("Failed to back up config into {:?}", backup_path))?;
+            write_default_config(&config_path)?;
+        }
+    }
+
+    pub fn get() -> Arc<Self> {
+        CONFIG.read().unwrap().clone().unwrap()
+    }
+
+    fn apply(&self, previous: &Self) -> Result<()> {
+        if self.auto_launch != previous.auto_launch {
+            set_auto_startup(self.auto_launch).context("Failed to set auto launch")?;
+        }
+        if self.port != previous.port || self.https_port != previous.https_port {
+            tls::init().context("Failed to load TLS certificate")?;
+            server::publish_server().context("Failed to restart API server")?;
+        }
+        if self.name != previous.name
+            || self.port != previous.port
+            || self.https_port != previous.https_port
+        {
+            mdns::publish_service().context("Failed to re-publish mDNS service")?;
+        }
+        Ok(())
+    }
+
+    pub fn reload() -> Result<()> {
+        let config = Arc::new(Self::new()?);
+        let previous = CONFIG.write().unwrap().replace(config.clone()).unwrap();
+
+        if let Err(error) = config.apply(&previous) {
+            *CONFIG.write().unwrap() = Some(previous.clone());
+            if let Err(error) = previous.apply(&config) {
+                error!("Failed to restore the previous config: {:?}", error);
+            }
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    pub fn watch() -> Result<()> {
+        let config_path = get_config_path().context("Failed to get config path")?;
+        let modified_time = move || metadata(&config_path).and_then(|metadata| metadata.modified());
+        let mut last_modified = modified_time().unwrap_or(SystemTime::UNIX_EPOCH);
+
+        thread::spawn(move || {
+            loop {
+                thread::sleep(WATCH_INTERVAL);
+                let Ok(modified) = modified_time() else {
+                    continue;
+                };
+                if modified == last_modified {
+                    continue;
+                }
+                last_modified = modified;
+
+                info!("\u{25CF} Config file changed, reloading.");
+                match Self::reload() {
+                    Ok(()) => show_notification(
+                        "AirDropPro Config Reloaded",
+                        "The new configuration is now active.",
+                    ),
+                    Err(error) => {
+                        error!("Failed to reload config: {:?}", error);
+                        show_notification(
+                            "AirDropPro Config Error",
+                            &format!("{:#}\nThe previous configuration stays active.", error),
+                        );
+                    }
+                }
+            }
+        });
+        Ok(())
     }
 }
@@ -0,0 +1,210 @@
+use crate::api::{ApiError, ErrorCode};
+use anyhow::Result;
+use unicode_normalization::UnicodeNormalization;
+
+// Leaves room for the counter appended when the name is already taken.
+const MAX_FILE_NAME_BYTES: usize = 200;
+const MAX_EXTENSION_BYTES: usize = 16;
+// Upload sessions, partial files and the write probe live next to received files.
+const RESERVED_PREFIX: &str = ".airdroppro-";
+
+const WINDOWS_RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Platform {
+    Windows,
+    MacOs,
+    Unix,
+}
+
+impl Platform {
+    fn current() -> Self {
+        if cfg!(target_os = "windows") {
+            Self::Windows
+        } else if cfg!(target_os = "macos") {
+            Self::MacOs
+        } else {
+            Self::Unix
+        }
+    }
+
+    fn is_illegal(self, c: char) -> bool {
+        if c.is_control() {
+            return true;
+        }
+        match self {
+            Self::Windows => matches!(c, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*'),
+            Self::MacOs => matches!(c, ':' | '/'),
+            Self::Unix => c == '/',
+        }
+    }
+}
+
+fn is_windows_reserved(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    WINDOWS_RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+}
+
+fn truncate_to_bytes(text: &str, max_bytes: usize) -> &str {
+    let mut end = max_bytes.min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+fn cap_length(name: String) -> String {
+    if name.len() <= MAX_FILE_NAME_BYTES {
+        return name;
+    }
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() && extension.len() < MAX_EXTENSION_BYTES => {
+            let stem = truncate_to_bytes(stem, MAX_FILE_NAME_BYTES - extension.len() - 1);
+            format!("{}.{}", stem.trim_end(), extension)
+        }
+        _ => truncate_to_bytes(&name, MAX_FILE_NAME_BYTES)
+            .trim_end()
+            .to_owned(),
+    }
+}
+
+fn sanitize_for(name: &str, platform: Platform) -> Result<String> {
+    let base_name = name
+        .rsplit(['/', '\\'])
+        .map(str::trim)
+        .find(|component| !component.is_empty())
+        .unwrap_or_default();
+
+    let mut sanitized: String = base_name
+        .nfc()
+        .map(|c| if platform.is_illegal(c) { '_' } else { c })
+        .collect();
+    if platform == Platform::Windows {
+        sanitized = sanitized.trim_end_matches(['.', ' ']).to_owned();
+        if is_windows_reserved(&sanitized) {
+            sanitized.insert(0, '_');
+        }
+    }
+    let sanitized = cap_length(sanitized);
+
+    if sanitized.chars().all(|c| c == '.' || c.is_whitespace()) {
+        return Err(ApiError::new(
+            ErrorCode::InvalidRequest,
+            format!("Invalid file name: {:?}", name),
+        )
+        .into());
+    }
+    if sanitized
+        .get(..RESERVED_PREFIX.len())
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(RESERVED_PREFIX))
+    {
+        return Err(ApiError::new(
+            ErrorCode::InvalidRequest,
+            format!("File name is reserved for AirDropPro: {:?}", name),
+        )
+        .into());
+    }
+    Ok(sanitized)
+}
+
+pub fn sanitize_file_name(name: &str) -> Result<String> {
+    sanitize_for(name, Platform::current())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sanitize(name: &str, platform: Platform) -> String {
+        sanitize_for(name, platform).unwrap()
+    }
+
+    #[test]
+    fn strips_directory_components() {
+        assert_eq!(sanitize("../../.bashrc", Platform::Unix), ".bashrc");
+        assert_eq!(sanitize("/etc/passwd", Platform::Unix), "passwd");
+        assert_eq!(
+            sanitize("C:\\Windows\\System32\\drivers", Platform::Windows),
+            "drivers"
+        );
+        assert_eq!(
+            sanitize("photos/IMG_0001.HEIC/", Platform::Unix),
+            "IMG_0001.HEIC"
+        );
+    }
+
+    #[test]
+    fn normalizes_unicode() {
+        let decomposed = "Cafe\u{0301}.txt";
+        assert_eq!(sanitize(decomposed, Platform::MacOs), "Caf\u{00E9}.txt");
+    }
+
+    #[test]
+    fn replaces_illegal_characters_per_platform() {
+        assert_eq!(
+            sanitize("a<b>c:d\"e|f?g*h.txt", Platform::Windows),
+            "a_b_c_d_e_f_g_h.txt"
+        );
+        assert_eq!(
+            sanitize("12:30 notes.txt", Platform::MacOs),
+            "12_30 notes.txt"
+        );
+        assert_eq!(
+            sanitize("12:30 <notes>.txt", Platform::Unix),
+            "12:30 <notes>.txt"
+        );
+        assert_eq!(sanitize("tab\there.txt", Platform::Unix), "tab_here.txt");
+    }
+
+    #[test]
+    fn escapes_windows_reserved_names() {
+        assert_eq!(sanitize("CON", Platform::Windows), "_CON");
+        assert_eq!(sanitize("com1.txt", Platform::Windows), "_com1.txt");
+        assert_eq!(sanitize("report. ", Platform::Windows), "report");
+        assert_eq!(sanitize("CONSOLE.txt", Platform::Windows), "CONSOLE.txt");
+        assert_eq!(sanitize("CON", Platform::Unix), "CON");
+    }
+
+    #[test]
+    fn caps_length_preserving_extension() {
+        let long_name = format!("{}.jpeg", "\u{00E9}".repeat(300));
+        let capped = sanitize(&long_name, Platform::Unix);
+        assert!(capped.len() <= MAX_FILE_NAME_BYTES);
+        assert!(capped.ends_with("\u{00E9}.jpeg"));
+
+        let long_extension = format!("name.{}", "x".repeat(300));
+        assert_eq!(
+            sanitize(&long_extension, Platform::Unix).len(),
+            MAX_FILE_NAME_BYTES
+        );
+    }
+
+    #[test]
+    fn rejects_empty_names() {
+        for name in ["", "   ", "..", "../..", "/", "...", "\\"] {
+            assert!(sanitize_for(name, Platform::Unix).is_err(), "{:?}", name);
+        }
+        assert!(sanitize_for("...", Platform::Windows).is_err());
+    }
+
+    #[test]
+    fn rejects_reserved_names() {
+        for name in [
+            ".airdroppro-uploads",
+            ".airdroppro-1234-0.part",
+            "photos/.AirDropPro-write-test",
+        ] {
+            assert!(sanitize_for(name, Platform::Unix).is_err(), "{:?}", name);
+        }
+        assert_eq!(sanitize(".airdroppro", Platform::Unix), ".airdroppro");
+        assert_eq!(
+            sanitize("airdroppro-notes.txt", Platform::Unix),
+            "airdroppro-notes.txt"
+        );
+    }
+}
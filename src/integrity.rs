@@ -0,0 +1,232 @@
+use crate::api::{ApiError, ErrorCode};
+use crate::utils::{get_config_dir, sha256_file};
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashSet, VecDeque};
+use std::fs::{Metadata, metadata, read_to_string, write};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::SystemTime;
+
+const MAX_CACHED_DIGESTS: usize = 1024;
+const DIGEST_FILE_NAME: &str = "digests.json";
+
+static DIGEST_CACHE: Mutex<Option<VecDeque<CachedDigest>>> = Mutex::new(None);
+static HASHING: Mutex<Option<HashSet<PathBuf>>> = Mutex::new(None);
+
+#[derive(Clone, Serialize, Deserialize)]
+struct CachedDigest {
+    path: PathBuf,
+    size: u64,
+    modified: Option<SystemTime>,
+    sha256: String,
+}
+
+impl CachedDigest {
+    fn matches(&self, path: &Path, metadata: &Metadata) -> bool {
+        self.path == path
+            && self.size == metadata.len()
+            && self.modified == metadata.modified().ok()
+    }
+}
+
+pub struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    pub fn inner_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    pub fn finish(self) -> String {
+        format!("{:x}", self.hasher.finalize())
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+fn decode_base64_digest(encoded: &str) -> Option<String> {
+    let bytes = general_purpose::STANDARD.decode(encoded.trim()).ok()?;
+    (bytes.len() == 32).then(|| bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+// Accepts hex, an RFC 9530 `sha-256=:<base64>:` member or a legacy `SHA-256=<base64>` value.
+pub fn parse_expected_hash(value: &str) -> Result<String> {
+    let value = value.trim();
+    let parsed = if value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(value.to_ascii_lowercase())
+    } else {
+        value.split(',').find_map(|member| {
+            let (algorithm, digest) = member.split_once('=')?;
+            if !algorithm.trim().eq_ignore_ascii_case("sha-256") {
+                return None;
+            }
+            let digest = digest.trim();
+            let digest = digest
+                .strip_prefix(':')
+                .and_then(|digest| digest.strip_suffix(':'))
+                .unwrap_or(digest);
+            decode_base64_digest(digest)
+        })
+    };
+    parsed.ok_or_else(|| {
+        ApiError::new(
+            ErrorCode::InvalidRequest,
+            format!("Invalid SHA-256 hash: {:?}", value),
+        )
+        .into()
+    })
+}
+
+pub fn verify(name: &str, expected: Option<&str>, actual: &str) -> Result<()> {
+    match expected {
+        Some(expected) if expected != actual => Err(ApiError::new(
+            ErrorCode::HashMismatch,
+            format!(
+                "Hash mismatch for {:?}, expected {} but got {}",
+                name, expected, actual
+            ),
+        )
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+fn digest_path() -> Result<PathBuf> {
+    Ok(get_config_dir()?.join(DIGEST_FILE_NAME))
+}
+
+fn load_digests() -> VecDeque<CachedDigest> {
+    let loaded = digest_path().and_then(|path| {
+        if !path.exists() {
+            return Ok(VecDeque::new());
+        }
+        let json = read_to_string(&path)
+            .with_context(|| format!("Failed to read digests from {:?}", path))?;
+        serde_json::from_str(&json).context("Failed to parse digests")
+    });
+    loaded.unwrap_or_else(|error| {
+        warn!("Failed to load digests: {:?}", error);
+        VecDeque::new()
+    })
+}
+
+fn save_digests(digests: &VecDeque<CachedDigest>) -> Result<()> {
+    let path = digest_path()?;
+    let json = serde_json::to_string(digests).context("Failed to serialize digests")?;
+    write(&path, json).with_context(|| format!("Failed to write digests to {:?}", path))
+}
+
+fn with_digests<T>(action: impl FnOnce(&mut VecDeque<CachedDigest>) -> T) -> T {
+    let mut digests = DIGEST_CACHE.lock().unwrap();
+    action(digests.get_or_insert_with(load_digests))
+}
+
+// Keyed by path, size and modification time, so an edited file is hashed again.
+pub fn remember_digest(path: &Path, sha256: &str) {
+    let Ok(metadata) = metadata(path) else {
+        return;
+    };
+    let saved = with_digests(|digests| {
+        digests.retain(|cached| cached.path != path);
+        if digests.len() >= MAX_CACHED_DIGESTS {
+            digests.pop_front();
+        }
+        digests.push_back(CachedDigest {
+            path: path.to_owned(),
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+            sha256: sha256.to_owned(),
+        });
+        save_digests(digests)
+    });
+    if let Err(error) = saved {
+        warn!("Failed to save digest of {:?}: {:?}", path, error);
+    }
+}
+
+pub fn cached_digest(path: &Path, metadata: &Metadata) -> Option<String> {
+    with_digests(|digests| {
+        digests
+            .iter()
+            .find(|cached| cached.matches(path, metadata))
+            .map(|cached| cached.sha256.clone())
+    })
+}
+
+// Hashes the file unless its digest is cached; only kept if the file did not change meanwhile.
+pub fn file_digest(path: &Path, metadata: &Metadata) -> Result<String> {
+    if let Some(sha256) = cached_digest(path, metadata) {
+        return Ok(sha256);
+    }
+    let sha256 = sha256_file(path)?;
+    let unchanged = std::fs::metadata(path).is_ok_and(|current| {
+        current.len() == metadata.len() && current.modified().ok() == metadata.modified().ok()
+    });
+    if unchanged {
+        remember_digest(path, &sha256);
+    }
+    Ok(sha256)
+}
+
+// Warms the cache for files about to be offered, so resumed downloads can carry a digest.
+pub fn hash_in_background(paths: Vec<PathBuf>) {
+    let paths: Vec<PathBuf> = {
+        let mut hashing = HASHING.lock().unwrap();
+        let hashing = hashing.get_or_insert_with(HashSet::new);
+        paths
+            .into_iter()
+            .filter(|path| {
+                metadata(path).is_ok_and(|metadata| {
+                    metadata.is_file() && cached_digest(path, &metadata).is_none()
+                })
+            })
+            .filter(|path| hashing.insert(path.clone()))
+            .collect()
+    };
+    if paths.is_empty() {
+        return;
+    }
+    thread::spawn(move || {
+        for path in paths {
+            let hashed = metadata(&path)
+                .context("Failed to read metadata")
+                .and_then(|metadata| file_digest(&path, &metadata));
+            if let Err(error) = hashed {
+                warn!("Failed to hash {:?}: {:?}", path, error);
+            }
+            if let Some(hashing) = HASHING.lock().unwrap().as_mut() {
+                hashing.remove(&path);
+            }
+        }
+    });
+}
+
+pub fn base64_digest(sha256: &str) -> String {
+    let bytes: Vec<u8> = (0..sha256.len())
+        .step_by(2)
+        .filter_map(|i| u8::from_str_radix(&sha256[i..i + 2], 16).ok())
+        .collect();
+    general_purpose::STANDARD.encode(bytes)
+}
@@ -0,0 +1,349 @@
+use crate::api::{ApiError, ErrorCode};
+use crate::config::Config;
+use crate::utils::{format_size, get_config_dir};
+use anyhow::{Context, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{read_to_string, write};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Free space is re-checked and quota reserved while streaming, once per this many bytes.
+const SPACE_CHECK_INTERVAL: u64 = 16 * 1024 * 1024;
+
+const QUOTA_FILE_NAME: &str = "upload_quota.json";
+
+static QUOTA_USAGE: Mutex<Option<QuotaUsage>> = Mutex::new(None);
+
+fn too_large(limit: u64, what: &str) -> anyhow::Error {
+    ApiError::new(
+        ErrorCode::PayloadTooLarge,
+        format!("The {} exceeds the {} limit", what, format_size(limit)),
+    )
+    .into()
+}
+
+pub fn check_file_size(size: u64) -> Result<()> {
+    let max_file_size = Config::get().max_file_size;
+    if max_file_size > 0 && size > max_file_size {
+        return Err(too_large(max_file_size, "file"));
+    }
+    Ok(())
+}
+
+pub fn check_free_space(destination: &Path, needed: u64) -> Result<()> {
+    let existing = destination
+        .ancestors()
+        .find(|path| path.exists())
+        .unwrap_or(destination);
+    let available = fs4::available_space(existing)
+        .with_context(|| format!("Failed to query free space of: {:?}", existing))?;
+    let required = needed.saturating_add(Config::get().min_free_space);
+    if available < required {
+        return Err(ApiError::new(
+            ErrorCode::InsufficientStorage,
+            format!(
+                "Not enough free space on the computer: {} available, {} required",
+                format_size(available),
+                format_size(required)
+            ),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct QuotaUsage {
+    // (Unix time in seconds, bytes) of every stored upload within the window.
+    stored: VecDeque<(u64, u64)>,
+    #[serde(skip)]
+    reserved: u64,
+}
+
+impl QuotaUsage {
+    fn prune(&mut self, window: Duration) {
+        let now = unix_time();
+        while self
+            .stored
+            .front()
+            .is_some_and(|(time, _)| now.saturating_sub(*time) > window.as_secs())
+        {
+            self.stored.pop_front();
+        }
+    }
+
+    fn used(&self) -> u64 {
+        self.stored.iter().map(|(_, bytes)| bytes).sum::<u64>() + self.reserved
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn quota_path() -> Result<PathBuf> {
+    Ok(get_config_dir()?.join(QUOTA_FILE_NAME))
+}
+
+fn load_usage() -> QuotaUsage {
+    let loaded = quota_path().and_then(|path| {
+        if !path.exists() {
+            return Ok(QuotaUsage::default());
+        }
+        let json = read_to_string(&path)
+            .with_context(|| format!("Failed to read upload quota usage from {:?}", path))?;
+        serde_json::from_str(&json).context("Failed to parse upload quota usage")
+    });
+    loaded.unwrap_or_else(|error| {
+        warn!("Failed to load upload quota usage: {:?}", error);
+        QuotaUsage::default()
+    })
+}
+
+fn save_usage(usage: &QuotaUsage) {
+    let saved = quota_path().and_then(|path| {
+        let json =
+            serde_json::to_string(usage).context("Failed to serialize upload quota usage")?;
+        write(&path, json)
+            .with_context(|| format!("Failed to write upload quota usage to {:?}", path))
+    });
+    if let Err(error) = saved {
+        warn!("Failed to save upload quota usage: {:?}", error);
+    }
+}
+
+fn with_usage<T>(action: impl FnOnce(&mut QuotaUsage, &Config) -> T) -> T {
+    let config = Config::get();
+    let mut usage = QUOTA_USAGE.lock().unwrap();
+    let usage = usage.get_or_insert_with(load_usage);
+    usage.prune(config.upload_quota_window);
+    action(usage, &config)
+}
+
+fn quota_exceeded() -> anyhow::Error {
+    let config = Config::get();
+    ApiError::new(
+        ErrorCode::QuotaExceeded,
+        format!(
+            "The upload quota of {} per {} hours is used up",
+            format_size(config.upload_quota),
+            config.upload_quota_window.as_secs().div_ceil(3600)
+        ),
+    )
+    .into()
+}
+
+pub fn check_quota(needed: u64) -> Result<()> {
+    with_usage(|usage, config| {
+        if config.upload_quota > 0 && usage.used().saturating_add(needed) > config.upload_quota {
+            return Err(quota_exceeded());
+        }
+        Ok(())
+    })
+}
+
+// Gives back the usage of stored files that were removed again, newest first.
+pub fn give_back_usage(mut bytes: u64) {
+    with_usage(|usage, _| {
+        while bytes > 0
+            && let Some((_, stored)) = usage.stored.back_mut()
+        {
+            let returned = bytes.min(*stored);
+            *stored -= returned;
+            bytes -= returned;
+            if *stored == 0 {
+                usage.stored.pop_back();
+            }
+        }
+        save_usage(usage);
+    })
+}
+
+// Quota held by a file while it is received; released on drop unless committed.
+#[derive(Default)]
+pub struct Reservation {
+    bytes: u64,
+}
+
+// Reserves quota for bytes that are already on disk, e.g. a finalized upload session.
+pub fn reserve(bytes: u64) -> Result<Reservation> {
+    let mut reservation = Reservation::default();
+    reservation.grow(bytes, 0)?;
+    Ok(reservation)
+}
+
+impl Reservation {
+    // Reserves `needed` more bytes plus up to `ahead` bytes of what is left of the quota.
+    fn grow(&mut self, needed: u64, ahead: u64) -> Result<()> {
+        with_usage(|usage, config| {
+            if config.upload_quota == 0 {
+                return Ok(());
+            }
+            let available = config.upload_quota.saturating_sub(usage.used());
+            if needed > available {
+                return Err(quota_exceeded());
+            }
+            let bytes = needed.saturating_add(ahead).min(available);
+            usage.reserved += bytes;
+            self.bytes += bytes;
+            Ok(())
+        })
+    }
+
+    // Gives back what was reserved ahead beyond the bytes actually received.
+    fn shrink_to(&mut self, bytes: u64) {
+        if self.bytes <= bytes {
+            return;
+        }
+        let released = self.bytes - bytes;
+        self.bytes = bytes;
+        with_usage(|usage, _| usage.reserved = usage.reserved.saturating_sub(released));
+    }
+
+    pub fn commit(mut self) {
+        let bytes = std::mem::take(&mut self.bytes);
+        if bytes == 0 {
+            return;
+        }
+        with_usage(|usage, _| {
+            usage.reserved = usage.reserved.saturating_sub(bytes);
+            usage.stored.push_back((unix_time(), bytes));
+            save_usage(usage);
+        })
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if self.bytes > 0 {
+            with_usage(|usage, _| usage.reserved = usage.reserved.saturating_sub(self.bytes));
+        }
+    }
+}
+
+pub struct UploadGuard {
+    max_file_size: u64,
+    max_request_size: u64,
+    upload_quota: u64,
+    content_length: Option<u64>,
+    received: u64,
+}
+
+impl UploadGuard {
+    pub fn new(content_length: Option<u64>) -> Result<Self> {
+        let config = Config::get();
+        let guard = Self {
+            max_file_size: config.max_file_size,
+            max_request_size: config.max_request_size,
+            upload_quota: config.upload_quota,
+            content_length,
+            received: 0,
+        };
+        if let Some(length) = content_length {
+            if guard.max_request_size > 0 && length > guard.max_request_size {
+                return Err(too_large(guard.max_request_size, "request"));
+            }
+            check_quota(length)?;
+        }
+        Ok(guard)
+    }
+
+    // Checks that the rest of the request fits where the next file is routed to.
+    pub fn check_destination(&self, destination: &Path) -> Result<()> {
+        match self.content_length {
+            Some(length) => check_free_space(destination, length.saturating_sub(self.received)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn reader<R: Read>(&mut self, inner: R, destination: &Path) -> GuardedReader<'_, R> {
+        GuardedReader {
+            guard: self,
+            inner,
+            destination: destination.to_owned(),
+            received: 0,
+            unchecked: 0,
+            reservation: Reservation::default(),
+            limit: 0,
+            error: None,
+        }
+    }
+}
+
+pub struct GuardedReader<'a, R> {
+    guard: &'a mut UploadGuard,
+    inner: R,
+    destination: PathBuf,
+    received: u64,
+    unchecked: u64,
+    reservation: Reservation,
+    limit: u64,
+    error: Option<anyhow::Error>,
+}
+
+impl<R> GuardedReader<'_, R> {
+    // Caps this stream below the configured limits, e.g. for content held in memory.
+    pub fn with_limit(mut self, limit: u64) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    pub fn take_reservation(&mut self) -> Reservation {
+        let mut reservation = std::mem::take(&mut self.reservation);
+        reservation.shrink_to(self.received);
+        reservation
+    }
+
+    // The limit that interrupted the stream, as opposed to an I/O failure.
+    pub fn take_error(&mut self) -> Option<anyhow::Error> {
+        self.error.take()
+    }
+
+    fn check(&mut self) -> Result<()> {
+        let guard = &self.guard;
+        if guard.max_file_size > 0 && self.received > guard.max_file_size {
+            return Err(too_large(guard.max_file_size, "file"));
+        }
+        if self.limit > 0 && self.received > self.limit {
+            return Err(too_large(self.limit, "file"));
+        }
+        if guard.max_request_size > 0 && guard.received > guard.max_request_size {
+            return Err(too_large(guard.max_request_size, "request"));
+        }
+        if guard.upload_quota > 0 && self.received > self.reservation.bytes {
+            self.reservation
+                .grow(self.received - self.reservation.bytes, SPACE_CHECK_INTERVAL)?;
+        }
+        if self.unchecked >= SPACE_CHECK_INTERVAL {
+            self.unchecked = 0;
+            check_free_space(&self.destination, SPACE_CHECK_INTERVAL)?;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for GuardedReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.received += read as u64;
+        self.unchecked += read as u64;
+        self.guard.received += read as u64;
+        if let Err(error) = self.check() {
+            let message = error.to_string();
+            self.error = Some(error);
+            return Err(io::Error::other(message));
+        }
+        Ok(read)
+    }
+}
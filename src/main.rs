@@ -2,11 +2,25 @@
 
 use crate::logger::LogAndExit;
 
+mod access;
+mod api;
+mod archive;
+mod clipboard_history;
+mod clipboard_sync;
 mod config;
+mod filename;
+mod integrity;
+mod limits;
 mod logger;
 mod mdns;
+mod routing;
 mod server;
+mod storage;
+mod tls;
+mod transfer_history;
+mod transfers;
 mod tray;
+mod upload;
 mod utils;
 
 fn main() {
@@ -14,11 +28,18 @@ fn main() {
 
     utils::ensure_single_instance("AirDropPro").log_and_exit("Failed to ensure single instance");
 
-    config::Config::init().log_and_exit("Failed to load config");
+    config::Config::init_or_restore().log_and_exit("Failed to load config");
+
+    tls::init().log_and_exit("Failed to load TLS certificate");
 
     mdns::publish_service().log_and_exit("Failed to publish mDNS service");
 
     server::publish_server().log_and_exit("Failed to publish API server");
 
+    upload::spawn_session_collector();
+    clipboard_sync::spawn_watcher();
+
+    config::Config::watch().log_and_exit("Failed to watch config file");
+
     tray::start_gui_tray().log_and_exit("Failed to start tray");
 }
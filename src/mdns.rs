@@ -1,12 +1,21 @@
+use crate::config::Config;
+use crate::tls;
 use anyhow::{Context, Result};
 use local_ip_address::local_ip;
 use log::{info, warn};
 use mdns_sd::{ServiceDaemon, ServiceInfo};
 use std::collections::HashMap;
 use std::net::IpAddr;
+use std::sync::Mutex;
 use std::thread;
 use std::time::Duration;
-use crate::config::Config;
+
+struct RegisteredService {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+static REGISTERED_SERVICE: Mutex<Option<RegisteredService>> = Mutex::new(None);
 
 pub fn wait_for_local_ip() -> Result<IpAddr> {
     let mut ip: Option<IpAddr> = None;
@@ -24,8 +33,9 @@ pub fn wait_for_local_ip() -> Result<IpAddr> {
 }
 
 pub fn publish_service() -> Result<()> {
-    let host_name = Config::get().name.as_str();
-    let port = Config::get().port;
+    let config = Config::get();
+    let host_name = config.name.as_str();
+    let port = config.port;
 
     let service_type = "_http._tcp.local.";
     let service_name = "AirDropPro";
@@ -41,19 +51,38 @@ pub fn publish_service() -> Result<()> {
         &format!("{}.local.", host_name)
     };
 
-    let mdns = ServiceDaemon::new().context("Failed to create service daemon")?;
-    let service_info = ServiceInfo::new(
-        service_type,
-        service_name,
-        host_name,
-        ip,
-        port,
-        HashMap::new(),
-    )
-    .context("Failed to create service")?;
+    let mut properties = HashMap::new();
+    if let (Some(https_port), Some(identity)) = (config.https_port, tls::identity()) {
+        properties.insert("https_port".to_owned(), https_port.to_string());
+        properties.insert("fingerprint".to_owned(), identity.fingerprint.clone());
+    }
+
+    let mut registered_service = REGISTERED_SERVICE.lock().unwrap();
+    let mdns = match registered_service.take() {
+        Some(previous) => {
+            info!(
+                "\u{2502} Unregistering previous service {:?}.",
+                previous.fullname
+            );
+            previous
+                .daemon
+                .unregister(&previous.fullname)
+                .context("Failed to unregister previous service")?;
+            previous.daemon
+        }
+        None => ServiceDaemon::new().context("Failed to create service daemon")?,
+    };
+    let service_info =
+        ServiceInfo::new(service_type, service_name, host_name, ip, port, properties)
+            .context("Failed to create service")?;
+    let fullname = service_info.get_fullname().to_owned();
 
     mdns.register(service_info)
         .context("Failed to register service")?;
+    *registered_service = Some(RegisteredService {
+        daemon: mdns,
+        fullname,
+    });
     info!("\u{2570} Service has been registered successfully!");
     Ok(())
 }
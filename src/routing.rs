@@ -0,0 +1,228 @@
+use crate::config::Config;
+use crate::filename::sanitize_file_name;
+use crate::utils::resolve_base_directory;
+use anyhow::Result;
+use chrono::{Local, NaiveDate};
+use log::warn;
+use std::path::{Path, PathBuf};
+
+const PLACEHOLDERS: [&str; 4] = ["{yyyy}", "{mm}", "{dd}", "{sender}"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RuleMatcher {
+    Mime(String),
+    Extension(String),
+    Sender(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutingRule {
+    matcher: RuleMatcher,
+    destination: String,
+}
+
+impl RoutingRule {
+    pub fn parse(pattern: &str, destination: &str) -> Result<Self, String> {
+        let pattern = pattern.trim().to_lowercase();
+        let matcher = if let Some(sender) = pattern.strip_prefix('@') {
+            RuleMatcher::Sender(sender.trim().to_owned())
+        } else if let Some(extension) = pattern
+            .strip_prefix("*.")
+            .or_else(|| pattern.strip_prefix('.'))
+        {
+            RuleMatcher::Extension(extension.to_owned())
+        } else if pattern.contains('/') {
+            RuleMatcher::Mime(pattern.clone())
+        } else {
+            return Err(format!(
+                "rule '{}' is not a MIME type (image/*), an extension (*.pdf) or a sender (@name)",
+                pattern
+            ));
+        };
+        let is_empty = match &matcher {
+            RuleMatcher::Sender(value) | RuleMatcher::Extension(value) => value.is_empty(),
+            RuleMatcher::Mime(_) => false,
+        };
+        if is_empty {
+            return Err(format!("rule '{}' has nothing to match", pattern));
+        }
+
+        let destination = destination.trim();
+        if destination.is_empty() {
+            return Err(format!("rule '{}' has no destination", pattern));
+        }
+        let mut rest = destination;
+        while let Some(start) = rest.find('{') {
+            rest = &rest[start..];
+            match PLACEHOLDERS
+                .iter()
+                .find(|placeholder| rest.starts_with(*placeholder))
+            {
+                Some(placeholder) => rest = &rest[placeholder.len()..],
+                None => {
+                    return Err(format!(
+                        "rule '{}' has an unknown placeholder in '{}', use {}",
+                        pattern,
+                        destination,
+                        PLACEHOLDERS.join(", ")
+                    ));
+                }
+            }
+        }
+
+        Ok(Self {
+            matcher,
+            destination: destination.to_owned(),
+        })
+    }
+
+    fn matches(&self, file_name: &str, sender: Option<&str>) -> bool {
+        match &self.matcher {
+            RuleMatcher::Mime(pattern) => mime_guess::from_path(file_name).iter().any(|mime| {
+                match pattern.strip_suffix("/*") {
+                    Some(top_level) => mime.type_() == top_level,
+                    None => mime.essence_str() == pattern,
+                }
+            }),
+            RuleMatcher::Extension(extension) => file_name
+                .to_lowercase()
+                .ends_with(&format!(".{}", extension)),
+            RuleMatcher::Sender(name) => {
+                sender.is_some_and(|sender| sender.to_lowercase() == *name)
+            }
+        }
+    }
+
+    // The base alias is looked up in the template itself, so a sender named like an
+    // alias cannot redirect uploads into that directory.
+    fn resolve(
+        &self,
+        sender: Option<&str>,
+        download_path: &Path,
+        today: NaiveDate,
+    ) -> Result<PathBuf> {
+        let sender = sender
+            .and_then(|sender| sanitize_file_name(sender).ok())
+            .unwrap_or_else(|| "unknown".to_owned());
+        let expand = |template: &str| {
+            template
+                .replace("{yyyy}", &today.format("%Y").to_string())
+                .replace("{mm}", &today.format("%m").to_string())
+                .replace("{dd}", &today.format("%d").to_string())
+                .replace("{sender}", &sender)
+        };
+
+        if Path::new(&self.destination).is_absolute() {
+            return Ok(PathBuf::from(expand(&self.destination)));
+        }
+        let (base, rest) = self
+            .destination
+            .split_once(['/', '\\'])
+            .unwrap_or((&self.destination, ""));
+        let base = match resolve_base_directory(base)? {
+            alias if alias.is_absolute() => alias,
+            _ => download_path.join(expand(base)),
+        };
+        Ok(base.join(expand(rest)))
+    }
+}
+
+pub fn destination_dir(file_name: &str, sender: Option<&str>) -> PathBuf {
+    let config = Config::get();
+    if let Some(rule) = config
+        .routing_rules
+        .iter()
+        .find(|rule| rule.matches(file_name, sender))
+    {
+        match rule.resolve(sender, &config.path, Local::now().date_naive()) {
+            Ok(destination) => return destination,
+            Err(error) => warn!(
+                "Failed to resolve routing destination {:?} for {:?}: {:?}",
+                rule.destination, file_name, error
+            ),
+        }
+    }
+    config.path.clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(pattern: &str, destination: &str) -> RoutingRule {
+        RoutingRule::parse(pattern, destination).unwrap()
+    }
+
+    fn resolve(rule: &RoutingRule, sender: Option<&str>) -> PathBuf {
+        let today = NaiveDate::from_ymd_opt(2024, 3, 7).unwrap();
+        rule.resolve(sender, Path::new("/downloads"), today)
+            .unwrap()
+    }
+
+    #[test]
+    fn matches_mime_types() {
+        assert!(rule("image/*", "photos").matches("IMG_0001.JPG", None));
+        assert!(rule("application/pdf", "papers").matches("paper.pdf", None));
+        assert!(!rule("image/*", "photos").matches("notes.txt", None));
+        assert!(!rule("image/png", "photos").matches("photo.jpg", None));
+    }
+
+    #[test]
+    fn matches_extensions() {
+        assert!(rule("*.pdf", "papers").matches("Paper.PDF", None));
+        assert!(rule(".tar.gz", "archives").matches("backup.tar.gz", None));
+        assert!(!rule("*.pdf", "papers").matches("pdf", None));
+    }
+
+    #[test]
+    fn matches_senders() {
+        let rule = rule("@Alice's iPhone", "alice");
+        assert!(rule.matches("photo.jpg", Some("alice's iphone")));
+        assert!(!rule.matches("photo.jpg", Some("bob")));
+        assert!(!rule.matches("photo.jpg", None));
+    }
+
+    #[test]
+    fn expands_date_and_sender() {
+        assert_eq!(
+            resolve(&rule("image/*", "photos/{yyyy}-{mm}-{dd}"), None),
+            Path::new("/downloads/photos/2024-03-07")
+        );
+        assert_eq!(
+            resolve(&rule("*.pdf", "{sender}/inbox"), Some("../Bob")),
+            Path::new("/downloads/Bob/inbox")
+        );
+        assert_eq!(
+            resolve(&rule("*.pdf", "{sender}"), None),
+            Path::new("/downloads/unknown")
+        );
+        assert_eq!(
+            resolve(&rule("*.pdf", "/srv/{yyyy}/{sender}"), Some("bob")),
+            Path::new("/srv/2024/bob")
+        );
+    }
+
+    #[test]
+    fn resolves_base_aliases_from_the_template_only() {
+        assert_eq!(
+            resolve(&rule("*.pdf", "{sender}/inbox"), Some("desktop")),
+            Path::new("/downloads/desktop/inbox")
+        );
+        if let Some(desktop) = dirs::desktop_dir() {
+            assert_eq!(
+                resolve(&rule("*.pdf", "desktop/{sender}"), Some("bob")),
+                desktop.join("bob")
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_bad_rules() {
+        assert!(RoutingRule::parse("pdf", "papers").is_err());
+        assert!(RoutingRule::parse("@", "papers").is_err());
+        assert!(RoutingRule::parse("*.", "papers").is_err());
+        assert!(RoutingRule::parse("*.pdf", " ").is_err());
+        assert!(RoutingRule::parse("*.pdf", "papers/{year}").is_err());
+        assert!(RoutingRule::parse("*.pdf", "papers/{sender").is_err());
+    }
+}
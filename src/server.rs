@@ -1,52 +1,283 @@
-use crate::config::Config;
+use crate::access::{grant_path, resolve_shared_path};
+use crate::api::{self, ApiError, ErrorCode};
+use crate::archive;
+use crate::clipboard_history;
+use crate::clipboard_sync;
+use crate::config::{ClipboardKind, Config, UploadErrorPolicy};
+use crate::integrity;
+use crate::limits::UploadGuard;
+use crate::storage::{self, ReceivedFile, StoreStatus, StoredFile};
+use crate::tls;
+use crate::transfer_history::{self, HistoryFile, Outcome, Peer};
+use crate::transfers::{self, Direction};
+use crate::upload;
 use crate::utils::{
-    base64_decode, base64_encode, clean_path_string, create_unique_file_path,
-    encode_image_to_base64_png, url_encode,
+    ByteRange, ImageEncoding, base64_decode, base64_encode, base64_encode_bytes, clean_path_string,
+    constant_time_eq, decode_base64_payload, decode_image, encode_image, parse_byte_range,
+    scaled_dimensions, show_notification, url_encode,
 };
 use anyhow::{Context, Result};
 use arboard::Clipboard;
-use log::{error, info};
-use notify_rust::Notification;
-use notify_rust::Timeout;
+use httpdate::fmt_http_date;
+use log::{error, info, warn};
 use regex::Regex;
-use rouille::{Request, Response, router};
+use rouille::input::multipart::MultipartError;
+use rouille::{Request, Response, ResponseBody, router};
+use serde::Serialize;
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{Read, Seek, SeekFrom};
 use std::path::PathBuf;
-use std::thread;
+use std::sync::Mutex;
+use std::sync::mpsc::Sender;
+use std::thread::{self, JoinHandle};
+use std::time::{Instant, UNIX_EPOCH};
+
+#[derive(Serialize)]
+#[serde(tag = "type", content = "data")]
+enum ClipboardContent {
+    #[serde(rename = "img")]
+    Image(String),
+    #[serde(rename = "img_url")]
+    ImageUrl(String),
+    #[serde(rename = "file")]
+    Files(Vec<String>),
+    #[serde(rename = "text")]
+    Text(String),
+}
+
+impl ClipboardContent {
+    fn describe(&self) -> String {
+        match self {
+            Self::Image(_) => "an image".to_owned(),
+            Self::ImageUrl(_) => "an image link".to_owned(),
+            Self::Files(paths) => format!("a file list with {} items", paths.len()),
+            Self::Text(_) => "text".to_owned(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct FileError {
+    code: ErrorCode,
+    msg: String,
+}
+
+#[derive(Serialize)]
+struct UploadedFile {
+    name: String,
+    stored_name: Option<String>,
+    path: Option<String>,
+    size: Option<u64>,
+    sha256: Option<String>,
+    status: StoreStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<FileError>,
+}
+
+impl UploadedFile {
+    fn new(name: String, stored_file: StoredFile) -> Self {
+        let config = Config::get();
+        let path = stored_file
+            .path
+            .strip_prefix(&config.path)
+            .unwrap_or(&stored_file.path);
+        Self {
+            name,
+            stored_name: stored_file
+                .path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned()),
+            path: Some(
+                path.components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/"),
+            ),
+            size: Some(stored_file.size),
+            sha256: Some(stored_file.sha256),
+            status: stored_file.status,
+            error: None,
+        }
+    }
+
+    fn not_stored(name: String, status: StoreStatus, error: Option<&anyhow::Error>) -> Self {
+        Self {
+            name,
+            stored_name: None,
+            path: None,
+            size: None,
+            sha256: None,
+            status,
+            error: error.map(|error| FileError {
+                code: error
+                    .downcast_ref::<ApiError>()
+                    .map_or(ErrorCode::Internal, |error| error.code),
+                msg: error.to_string(),
+            }),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AuthInfo {
+    required: bool,
+    header: &'static str,
+    query: &'static str,
+}
+
+#[derive(Serialize)]
+struct ServerInfo {
+    name: String,
+    version: &'static str,
+    api_versions: &'static [&'static str],
+    os: &'static str,
+    clipboard_types: &'static [&'static str],
+    max_upload_size: Option<u64>,
+    https_port: Option<u16>,
+    auth: AuthInfo,
+}
+
+// Clipboard images are decoded in memory, so they are capped regardless of 'max file size'.
+const MAX_CLIPBOARD_IMAGE_SIZE: u64 = 64 * 1024 * 1024;
+// Unauthorized requests are logged at most this often.
+const UNAUTHORIZED_LOG_INTERVAL_SECS: u64 = 60;
+
+static RUNNING_SERVERS: Mutex<Vec<(JoinHandle<()>, Sender<()>)>> = Mutex::new(Vec::new());
+static UNAUTHORIZED_LOG: Mutex<Option<(Instant, u64)>> = Mutex::new(None);
 
 fn success(msg: &str) {
     info!("\u{2570} Response: {}.", msg);
-    Notification::new()
-        .appname("app.airdroppro.desktop")
-        .summary("AirDropPro Success")
-        .body(msg)
-        .timeout(Timeout::Milliseconds(5000))
-        .show()
-        .unwrap();
+    show_notification("AirDropPro Success", msg);
 }
 
 fn failed(err: anyhow::Error) -> Response {
+    let code = err
+        .downcast_ref::<ApiError>()
+        .map_or(ErrorCode::Internal, |error| error.code);
     let user_msg = err.to_string();
-    let json_msg = format!(r#"{{"success": false, "msg": "{}."}}"#, user_msg);
+
+    // Anyone on the network can send unauthenticated requests, so they are only logged.
+    if code == ErrorCode::Unauthorized {
+        log_unauthorized(&user_msg);
+        return api::error(code, user_msg);
+    }
 
     error!("Error: {:?}", err);
 
-    Notification::new()
-        .appname("app.airdroppro.desktop")
-        .summary("AirDropPro Error")
-        .body(&user_msg)
-        .timeout(Timeout::Milliseconds(5000))
-        .show()
-        .unwrap();
+    show_notification("AirDropPro Error", &user_msg);
 
-    Response::json(&json_msg).with_status_code(500)
+    api::error(code, user_msg)
 }
 
-fn get_file_handler(_request: &Request, encoded_filepath: String) -> Result<Response> {
-    let filepath_str = base64_decode(&encoded_filepath).context("Failed to decode the URL path")?;
+fn request_sender(request: &Request) -> String {
+    request
+        .header("ShortcutDevice")
+        .map(str::to_owned)
+        .unwrap_or_else(|| request.remote_addr().ip().to_string())
+}
+
+fn request_peer(request: &Request) -> Peer {
+    Peer {
+        ip: request.remote_addr().ip().to_string(),
+        device: request.header("ShortcutDevice").map(str::to_owned),
+    }
+}
 
-    let filepath = PathBuf::from(&filepath_str);
+fn log_unauthorized(message: &str) {
+    let mut last_log = UNAUTHORIZED_LOG.lock().unwrap();
+    match last_log.as_mut() {
+        Some((logged, suppressed))
+            if logged.elapsed().as_secs() < UNAUTHORIZED_LOG_INTERVAL_SECS =>
+        {
+            *suppressed += 1;
+        }
+        _ => {
+            match last_log
+                .map(|(_, suppressed)| suppressed)
+                .filter(|n| *n > 0)
+            {
+                Some(suppressed) => warn!(
+                    "Rejected unauthorized request: {} ({} more since the last report)",
+                    message, suppressed
+                ),
+                None => warn!("Rejected unauthorized request: {}", message),
+            }
+            *last_log = Some((Instant::now(), 0));
+        }
+    }
+}
+
+fn authorize(request: &Request) -> Result<()> {
+    let config = Config::get();
+    if config.allow_anonymous {
+        return Ok(());
+    }
+
+    let provided_key = request
+        .header("ShortcutKey")
+        .map(str::to_owned)
+        .or_else(|| request.get_param("key"));
+    match provided_key {
+        Some(key) if constant_time_eq(key.as_bytes(), config.key.as_bytes()) => Ok(()),
+        Some(_) => Err(ApiError::new(ErrorCode::Unauthorized, "Invalid key").into()),
+        None => Err(ApiError::new(ErrorCode::Unauthorized, "Missing key").into()),
+    }
+}
+
+fn resolve_encoded_path(encoded_filepath: &str) -> Result<PathBuf> {
+    let filepath_str = base64_decode(encoded_filepath).context("Failed to decode the URL path")?;
+
+    resolve_shared_path(&filepath_str).ok_or_else(|| {
+        ApiError::new(
+            ErrorCode::PathNotShared,
+            format!("Path is not inside a shared root: {:?}", filepath_str),
+        )
+        .into()
+    })
+}
+
+fn attachment_header(filename: &str) -> String {
+    format!("attachment; filename*=UTF-8''{}", url_encode(filename))
+}
+
+fn zip_response(request: &Request, paths: Vec<PathBuf>) -> Result<Response> {
+    let archive_name = archive::archive_name(&paths);
+    let reader = archive::stream_zip(paths.clone());
+    let transfer = transfers::start(
+        Direction::Download,
+        &archive_name,
+        request_peer(request),
+        paths,
+        None,
+    );
+
+    success(&format!("Started streaming zip archive {:?}", archive_name));
+    Ok(Response {
+        status_code: 200,
+        headers: vec![("Content-Type".into(), "application/zip".into())],
+        data: ResponseBody::from_reader(transfer.reader(reader)),
+        upgrade: None,
+    }
+    .with_additional_header("Content-Disposition", attachment_header(&archive_name)))
+}
+
+fn get_archive_handler(request: &Request, encoded_paths: String) -> Result<Response> {
+    let paths = encoded_paths
+        .split(',')
+        .filter(|encoded_path| !encoded_path.is_empty())
+        .map(resolve_encoded_path)
+        .collect::<Result<Vec<_>>>()?;
+    if paths.is_empty() {
+        return Err(ApiError::new(ErrorCode::InvalidRequest, "No paths to archive").into());
+    }
+    zip_response(request, paths)
+}
+
+fn get_file_handler(request: &Request, encoded_filepath: String) -> Result<Response> {
+    let filepath = resolve_encoded_path(&encoded_filepath)?;
+    if filepath.is_dir() {
+        return zip_response(request, vec![filepath]);
+    }
     let filename = filepath
         .file_name()
         .and_then(|name| name.to_str())
@@ -55,209 +286,1129 @@ fn get_file_handler(_request: &Request, encoded_filepath: String) -> Result<Resp
             filepath
         ))?;
 
-    let file = File::open(&filepath)
+    let mut file = File::open(&filepath)
         .context(format!("Failed to open the file at path: {:?}", filepath))?;
+    let metadata = file
+        .metadata()
+        .context(format!("Failed to read metadata of file: {:?}", filepath))?;
+    let file_size = metadata.len();
+    let modified = metadata.modified().context(format!(
+        "Failed to read modification time of file: {:?}",
+        filepath
+    ))?;
+    let etag = format!(
+        "\"{:x}-{:x}\"",
+        file_size,
+        modified
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    );
+    let last_modified = fmt_http_date(modified);
 
     let mime_type = mime_guess::from_path(&filepath)
         .first_or_octet_stream()
         .to_string();
-    let content_disposition_header =
-        format!("attachment; filename*=UTF-8''{}", url_encode(filename));
+    let content_disposition_header = attachment_header(filename);
 
-    success(&format!(
-        "Successfully served file from path: {:?}",
-        filepath
-    ));
-    Ok(Response::from_file(mime_type, file)
-        .with_additional_header("Content-Disposition", content_disposition_header))
+    let if_range_matches = request
+        .header("If-Range")
+        .is_none_or(|validator| validator == etag || validator == last_modified);
+    let byte_range = match request.header("Range") {
+        Some(range) if if_range_matches => parse_byte_range(range, file_size),
+        _ => ByteRange::Full,
+    };
+
+    let transfer = transfers::start(
+        Direction::Download,
+        filename,
+        request_peer(request),
+        vec![filepath.clone()],
+        Some(file_size),
+    );
+    // A full download may hash the file first, a range only uses a digest that is already known.
+    let digest = match byte_range {
+        ByteRange::Full => Some(integrity::file_digest(&filepath, &metadata)?),
+        _ => integrity::cached_digest(&filepath, &metadata),
+    }
+    .map(|sha256| integrity::base64_digest(&sha256));
+
+    let response = match byte_range {
+        ByteRange::Full => {
+            success(&format!(
+                "Successfully served file from path: {:?}",
+                filepath
+            ));
+            Response {
+                status_code: 200,
+                headers: vec![("Content-Type".into(), mime_type.into())],
+                data: ResponseBody::from_reader_and_size(transfer.reader(file), file_size as usize),
+                upgrade: None,
+            }
+            .with_etag(request, etag)
+        }
+        ByteRange::Partial(start, end) => {
+            file.seek(SeekFrom::Start(start))
+                .context(format!("Failed to seek in file: {:?}", filepath))?;
+            let length = end - start + 1;
+            success(&format!(
+                "Successfully served bytes {}-{} of file from path: {:?}",
+                start, end, filepath
+            ));
+            Response {
+                status_code: 206,
+                headers: vec![("Content-Type".into(), mime_type.into())],
+                data: ResponseBody::from_reader_and_size(
+                    transfer.reader(file.take(length)),
+                    length as usize,
+                ),
+                upgrade: None,
+            }
+            .with_unique_header(
+                "Content-Range",
+                format!("bytes {}-{}/{}", start, end, file_size),
+            )
+            .with_etag_keep(etag)
+        }
+        ByteRange::Unsatisfiable => {
+            info!("\u{2570} Response: unsatisfiable range for {:?}.", filepath);
+            return Ok(api::error(
+                ErrorCode::RangeNotSatisfiable,
+                "Requested range not satisfiable",
+            )
+            .with_unique_header("Content-Range", format!("bytes */{}", file_size)));
+        }
+    };
+
+    let mut response = response
+        .with_unique_header("Accept-Ranges", "bytes")
+        .with_unique_header("Last-Modified", last_modified)
+        .with_additional_header("Content-Disposition", content_disposition_header);
+    if let Some(digest) = digest {
+        response = response
+            .with_unique_header("Repr-Digest", format!("sha-256=:{}:", digest))
+            .with_unique_header("Digest", format!("SHA-256={}", digest));
+    }
+    Ok(response)
+}
+
+fn describe_stored_file(original_filename: &str, stored_file: &StoredFile) -> String {
+    match stored_file.status {
+        StoreStatus::Stored => format!(
+            "Successfully uploaded file {:?} to path: {:?}",
+            original_filename, stored_file.path
+        ),
+        StoreStatus::Overwritten => format!(
+            "Successfully uploaded file {:?}, overwriting: {:?}",
+            original_filename, stored_file.path
+        ),
+        StoreStatus::Skipped => format!(
+            "Skipped file {:?}, a file already exists at: {:?}",
+            original_filename, stored_file.path
+        ),
+        StoreStatus::AlreadyPresent => format!(
+            "File {:?} is already present at: {:?}",
+            original_filename, stored_file.path
+        ),
+        StoreStatus::RolledBack | StoreStatus::Failed => {
+            format!("File {:?} was not stored", original_filename)
+        }
+    }
+}
+
+fn content_length(request: &Request) -> Option<u64> {
+    request
+        .header("Content-Length")
+        .and_then(|length| length.trim().parse().ok())
+}
+
+fn expected_file_hash(
+    field_hash: Option<String>,
+    header_hash: Option<&String>,
+    is_first_file: bool,
+) -> Result<Option<String>> {
+    match (field_hash, header_hash) {
+        (Some(hash), _) => Ok(Some(hash)),
+        (None, Some(hash)) if is_first_file => Ok(Some(hash.clone())),
+        (None, Some(_)) => Err(ApiError::new(
+            ErrorCode::InvalidRequest,
+            "The ShortcutHash header covers a single file, send a sha256 field before each file instead",
+        )
+        .into()),
+        (None, None) => Ok(None),
+    }
+}
+
+fn record_upload(request: &Request, results: &[UploadedFile], outcome: Outcome) {
+    let config = Config::get();
+    let files = results
+        .iter()
+        .map(|file| HistoryFile {
+            name: file.name.clone(),
+            path: file.path.as_ref().map(|path| config.path.join(path)),
+            size: file.size,
+            status: Some(file.status),
+        })
+        .collect();
+    transfer_history::record(Direction::Upload, request_peer(request), files, outcome);
 }
 
 fn post_file_handler(request: &Request) -> Result<Response> {
+    let mut results = Vec::new();
+    // Requests rejected before or between files are recorded with what arrived so far.
+    receive_files(request, &mut results).inspect_err(|error| {
+        let stored = results
+            .iter()
+            .any(|file| matches!(file.status, StoreStatus::Stored | StoreStatus::Overwritten));
+        let outcome = match error.downcast_ref::<ApiError>().map(|error| error.code) {
+            Some(ErrorCode::Cancelled) => Outcome::Cancelled,
+            _ if stored => Outcome::Partial,
+            _ => Outcome::Failed,
+        };
+        record_upload(request, &results, outcome);
+    })
+}
+
+fn receive_files(request: &Request, results: &mut Vec<UploadedFile>) -> Result<Response> {
+    let sender = request_sender(request);
+    let rollback = Config::get().on_upload_error == UploadErrorPolicy::Rollback;
+    let mut guard = UploadGuard::new(content_length(request))?;
+    let header_hash = request
+        .header("ShortcutHash")
+        .map(integrity::parse_expected_hash)
+        .transpose()?;
     let mut multipart_data = rouille::input::multipart::get_multipart_input(request)
         .context("Failed to parse multipart input")?;
-    let destination_path = &Config::get().path;
+    let mut stored_files = Vec::new();
+    let mut received_files = Vec::new();
+    let mut first_error = None;
+    let mut field_hash = None;
 
     while let Some(mut field) = multipart_data.next() {
-        if let Some(original_filename) = field.headers.filename {
-            let unique_filepath = create_unique_file_path(destination_path, &original_filename)
-                .context(format!(
-                    "Failed to create unique filepath in directory: {:?}",
-                    destination_path
-                ))?;
-
-            let mut file_buffer: Vec<u8> = Vec::new();
-            field
-                .data
-                .read_to_end(&mut file_buffer)
-                .context("Failed to read the uploaded file content")?;
-
-            let mut new_file = File::create(&unique_filepath).context(format!(
-                "Failed to create a new file at: {:?}",
-                unique_filepath
-            ))?;
-
-            new_file.write_all(&file_buffer).context(format!(
-                "Failed to write data to file: {:?}",
-                unique_filepath
-            ))?;
+        match field.headers.filename.clone() {
+            Some(original_filename) => {
+                // Parts carry no length of their own, so each file is tracked without a total.
+                let transfer = transfers::start(
+                    Direction::Upload,
+                    &original_filename,
+                    request_peer(request),
+                    Vec::new(),
+                    None,
+                );
+                let received_file =
+                    expected_file_hash(field_hash.take(), header_hash.as_ref(), results.is_empty())
+                        .and_then(|expected_hash| {
+                            storage::receive_file(
+                                &mut transfer.reader(&mut field.data),
+                                &original_filename,
+                                Some(&sender),
+                                &mut guard,
+                                expected_hash.as_deref(),
+                            )
+                        });
+                let outcome = match received_file {
+                    Ok(received_file) if rollback => {
+                        received_files.push((results.len(), received_file));
+                        results.push(UploadedFile::not_stored(
+                            original_filename,
+                            StoreStatus::RolledBack,
+                            None,
+                        ));
+                        continue;
+                    }
+                    Ok(received_file) => received_file.store(),
+                    Err(error) => Err(error),
+                };
+                match outcome {
+                    Ok(stored_file) => {
+                        success(&describe_stored_file(&original_filename, &stored_file));
+                        results.push(UploadedFile::new(original_filename, stored_file));
+                    }
+                    Err(error) => {
+                        error!("Failed to upload file {:?}: {:?}", original_filename, error);
+                        results.push(UploadedFile::not_stored(
+                            original_filename,
+                            StoreStatus::Failed,
+                            Some(&error),
+                        ));
+                        let cancelled = error
+                            .downcast_ref::<ApiError>()
+                            .is_some_and(|error| error.code == ErrorCode::Cancelled);
+                        first_error.get_or_insert(error);
+                        // Cancelling one file from the tray stops the rest of the request too.
+                        if rollback || cancelled {
+                            break;
+                        }
+                    }
+                }
+            }
+            None if &*field.headers.name == "sha256" => {
+                let mut value = String::new();
+                field
+                    .data
+                    .read_to_string(&mut value)
+                    .context("Failed to read form field")?;
+                field_hash = Some(integrity::parse_expected_hash(&value)?);
+            }
+            None => {}
+        }
+    }
 
-            success(&format!(
-                "Successfully uploaded file '{:?}' to path: {:?}",
-                original_filename, unique_filepath
-            ));
+    // In rollback mode nothing is moved into place until every file has arrived intact.
+    if rollback && first_error.is_none() {
+        for (index, received_file) in received_files.drain(..) {
+            let name = results[index].name.clone();
+            match received_file.store() {
+                Ok(stored_file) => stored_files.push((index, stored_file)),
+                Err(error) => {
+                    error!("Failed to upload file {:?}: {:?}", name, error);
+                    results[index] =
+                        UploadedFile::not_stored(name, StoreStatus::Failed, Some(&error));
+                    first_error = Some(error);
+                    break;
+                }
+            }
+        }
+        for (index, mut stored_file) in stored_files {
+            let name = results[index].name.clone();
+            if first_error.is_some()
+                && let Err(error) = storage::roll_back(&mut stored_file)
+            {
+                warn!("{:?}", error);
+            }
+            results[index] = if stored_file.status == StoreStatus::RolledBack {
+                UploadedFile::not_stored(name, StoreStatus::RolledBack, None)
+            } else {
+                success(&describe_stored_file(&name, &stored_file));
+                UploadedFile::new(name, stored_file)
+            };
+        }
+    }
+
+    let count = |status: StoreStatus| results.iter().filter(|file| file.status == status).count();
+    let stored = count(StoreStatus::Stored) + count(StoreStatus::Overwritten);
+    if let Some(error) = first_error {
+        let code = error
+            .downcast_ref::<ApiError>()
+            .map_or(ErrorCode::Internal, |error| error.code);
+        let msg = if rollback {
+            format!(
+                "Upload failed, rolled back {} file(s): {}",
+                count(StoreStatus::RolledBack),
+                error
+            )
+        } else {
+            format!(
+                "Uploaded {} of {} file(s), {} failed: {}",
+                stored,
+                results.len(),
+                count(StoreStatus::Failed),
+                error
+            )
+        };
+        show_notification("AirDropPro Error", &msg);
+        let outcome = match code {
+            ErrorCode::Cancelled => Outcome::Cancelled,
+            _ if stored > 0 => Outcome::Partial,
+            _ => Outcome::Failed,
+        };
+        record_upload(request, results, outcome);
+        // 207 tells the client that some files did arrive despite the error.
+        let status = if stored > 0 { 207 } else { code.status() };
+        return Ok(api::error_with_data(code, msg, &*results).with_status_code(status));
+    }
+
+    let mut msg = format!("Successfully uploaded {} file(s)", stored);
+    for (status, label) in [
+        (StoreStatus::AlreadyPresent, "already present"),
+        (StoreStatus::Skipped, "skipped"),
+    ] {
+        if count(status) > 0 {
+            msg.push_str(&format!(", {} {}", count(status), label));
         }
     }
-    Ok(Response::json(&r#"{"success": true}"#))
+    record_upload(request, results, Outcome::Completed);
+    Ok(api::ok(msg, &*results))
 }
 
-fn get_clipboard_handler(_request: &Request) -> Result<Response> {
-    let mut clipboard = Clipboard::new().context("Failed to initialize clipboard")?;
+fn post_upload_handler(request: &Request) -> Result<Response> {
+    let post_data = rouille::post_input!(request, {
+        name: String,
+        size: u64,
+        hash: Option<String>,
+    })
+    .context("Failed to parse POST input")?;
+
+    let session = upload::create_session(&post_data.name, post_data.size, post_data.hash)
+        .context("Failed to create upload session")?;
+    Ok(api::ok("Upload session created", session))
+}
+
+fn get_upload_handler(_request: &Request, id: String) -> Result<Response> {
+    let session = upload::get_session(&id).context("Failed to query upload session")?;
+    Ok(api::ok("Upload session found", session))
+}
+
+fn put_upload_handler(request: &Request, id: String) -> Result<Response> {
+    let offset = request
+        .get_param("offset")
+        .context("Missing 'offset' query parameter")?;
+    let offset = offset
+        .parse::<u64>()
+        .with_context(|| format!("Failed to parse 'offset' value '{}'", offset))?;
+    let body = request.data().context("Request body already consumed")?;
 
-    if let Ok(image) = clipboard.get().image() {
-        let base64_image_data =
-            encode_image_to_base64_png(image).context("Failed to encode image to base64")?;
+    let session = upload::write_chunk(&id, offset, body).context("Failed to write chunk")?;
+    Ok(api::ok("Chunk received", session))
+}
+
+fn finalize_upload_handler(request: &Request, id: String) -> Result<Response> {
+    let stored_file = upload::finalize_session(&id, Some(&request_sender(request)))
+        .context("Failed to finalize upload session")?;
+
+    let msg = describe_stored_file(&stored_file.name, &stored_file);
+    success(&msg);
+    let uploaded_file = UploadedFile::new(stored_file.name.clone(), stored_file);
+    record_upload(
+        request,
+        std::slice::from_ref(&uploaded_file),
+        Outcome::Completed,
+    );
+    Ok(api::ok(msg, uploaded_file))
+}
 
-        success("Successfully served clipboard content as an image");
-        return Ok(Response::json(&format!(
-            r#"{{"success": true, "data": {{"type": "img", "data": {:?}}}}}"#,
-            base64_image_data
-        )));
+// Only a copied file list grants download access; links scraped from HTML do not.
+fn share_clipboard_files(
+    file_paths: Vec<String>,
+    record: bool,
+    grant: bool,
+) -> Result<Option<ClipboardContent>> {
+    if file_paths.is_empty() {
+        return Ok(None);
     }
+    let mut encoded_file_paths = Vec::new();
+    for path_str in &file_paths {
+        if grant && let Err(error) = grant_path(path_str) {
+            warn!("Failed to share clipboard file {:?}: {:?}", path_str, error);
+        }
+        let encoded =
+            base64_encode(path_str).context(format!("Failed to encode path: {}", path_str))?;
+        encoded_file_paths.push(encoded);
+    }
+    integrity::hash_in_background(file_paths.iter().map(PathBuf::from).collect());
+    if record {
+        record_history(clipboard_history::record_files(&file_paths));
+    }
+    Ok(Some(ClipboardContent::Files(encoded_file_paths)))
+}
 
-    if let Ok(file_list) = clipboard.get().file_list() {
-        let mut encoded_file_paths = Vec::new();
-        for path in file_list {
-            if let Some(path_str) = path.to_str() {
-                let encoded_path = base64_encode(clean_path_string(path_str))
-                    .context("Failed to encode file path")?;
-                encoded_file_paths.push(format!("{:?}", encoded_path));
+fn html_file_paths(html: &str) -> Result<Vec<String>> {
+    let regex_for_file_urls = Regex::new(r#"src="(?P<path>file:///[^"]+)""#)?;
+    let mut file_paths = Vec::new();
+    for captures in regex_for_file_urls.captures_iter(html) {
+        if let Some(path_match) = captures.name("path") {
+            let mut path_str = clean_path_string(path_match.as_str());
+            if path_str.starts_with("file:///") {
+                path_str = &path_str[7..];
             }
+            #[cfg(target_os = "windows")]
+            {
+                if path_str.starts_with('/') {
+                    path_str = &path_str[1..];
+                }
+            }
+            file_paths.push(path_str.to_owned());
         }
+    }
+    Ok(file_paths)
+}
 
-        if !encoded_file_paths.is_empty() {
-            let paths_json_string = encoded_file_paths.join(",");
+fn probe_clipboard(
+    clipboard: &mut Clipboard,
+    kind: ClipboardKind,
+    record: bool,
+) -> Result<Option<ClipboardContent>> {
+    match kind {
+        ClipboardKind::Image => {
+            let Ok(image) = clipboard.get().image() else {
+                return Ok(None);
+            };
+            if record {
+                record_history(clipboard_history::record_image(&image));
+            }
+            let config = Config::get();
+            // Decided on the pixel data, so a large image is not encoded just to be linked.
+            let (width, height) =
+                scaled_dimensions(image.width, image.height, config.clipboard_image_max_size);
+            let inline_limit = config.clipboard_image_inline_limit as u64;
+            if inline_limit > 0 && width * height * 4 > inline_limit {
+                return Ok(Some(ClipboardContent::ImageUrl(
+                    "/api/v1/clipboard/image".to_owned(),
+                )));
+            }
+            let png = encode_image(
+                &image,
+                ImageEncoding::Png,
+                config.clipboard_image_max_size,
+                config.clipboard_image_quality,
+            )
+            .context("Failed to encode clipboard image")?;
+            Ok(Some(ClipboardContent::Image(base64_encode_bytes(&png))))
+        }
+        ClipboardKind::Files => {
+            let Ok(file_list) = clipboard.get().file_list() else {
+                return Ok(None);
+            };
+            let file_paths = file_list
+                .iter()
+                .filter_map(|path| path.to_str())
+                .map(|path_str| clean_path_string(path_str).to_owned())
+                .collect();
+            share_clipboard_files(file_paths, record, true)
+        }
+        ClipboardKind::HtmlFiles => {
+            let Ok(html) = clipboard.get().html() else {
+                return Ok(None);
+            };
+            share_clipboard_files(html_file_paths(&html)?, record, false)
+        }
+        ClipboardKind::Text => {
+            let Ok(text) = clipboard.get().text() else {
+                return Ok(None);
+            };
+            if record {
+                record_history(clipboard_history::record_text(&text));
+            }
+            Ok(Some(ClipboardContent::Text(text)))
+        }
+    }
+}
 
-            success(&format!(
-                "Successfully served clipboard content as a file list with {} items",
-                encoded_file_paths.len()
-            ));
-            return Ok(Response::json(&format!(
-                r#"{{"success": true, "data": {{"type": "file", "data": [{}]}}}}"#,
-                paths_json_string
-            )));
+fn clipboard_priority(request: &Request) -> Result<Vec<ClipboardKind>> {
+    let enabled = Config::get().clipboard_priority.clone();
+    let Some(prefer) = request.get_param("prefer") else {
+        return Ok(enabled);
+    };
+
+    let mut priority = Vec::new();
+    for name in prefer
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        let kind = ClipboardKind::from_name(name).ok_or_else(|| {
+            let msg = match name {
+                "html" => "Unknown clipboard type: \"html\", use \"html_links\" for file links in copied HTML".to_owned(),
+                _ => format!("Unknown clipboard type: {:?}", name),
+            };
+            ApiError::new(ErrorCode::InvalidRequest, msg)
+        })?;
+        if enabled.contains(&kind) && !priority.contains(&kind) {
+            priority.push(kind);
+        }
+    }
+    for kind in enabled {
+        if !priority.contains(&kind) {
+            priority.push(kind);
+        }
+    }
+    Ok(priority)
+}
+
+fn get_clipboard_handler(request: &Request) -> Result<Response> {
+    let priority = clipboard_priority(request)?;
+    let mut clipboard = Clipboard::new().context("Failed to initialize clipboard")?;
+
+    for kind in priority {
+        if let Some(content) = probe_clipboard(&mut clipboard, kind, true)? {
+            let msg = format!(
+                "Successfully served clipboard content as {}",
+                content.describe()
+            );
+            match &content {
+                ClipboardContent::Text(text) => success(&format!("{}: {:?}", msg, text)),
+                _ => success(&msg),
+            }
+            return Ok(api::ok(msg, content));
+        }
+    }
+
+    anyhow::bail!("Unsupported clipboard format");
+}
+
+fn negotiate_image_encoding(request: &Request) -> Result<ImageEncoding> {
+    if let Some(format) = request.get_param("format") {
+        return ImageEncoding::from_name(&format).ok_or_else(|| {
+            ApiError::new(
+                ErrorCode::InvalidRequest,
+                format!("Unsupported image format: {:?}", format),
+            )
+            .into()
+        });
+    }
+
+    let encodings = [ImageEncoding::Png, ImageEncoding::Jpeg, ImageEncoding::WebP];
+    let accept = request.header("Accept").unwrap_or("*/*");
+    Ok(rouille::input::priority_header_preferred(
+        accept,
+        encodings.iter().map(|encoding| encoding.mime()),
+    )
+    .map_or(ImageEncoding::Png, |index| encodings[index]))
+}
+
+fn get_clipboard_image_handler(request: &Request) -> Result<Response> {
+    let encoding = negotiate_image_encoding(request)?;
+    let mut clipboard = Clipboard::new().context("Failed to initialize clipboard")?;
+    let image = clipboard
+        .get()
+        .image()
+        .map_err(|_| ApiError::new(ErrorCode::NotFound, "Clipboard does not contain an image"))?;
+    record_history(clipboard_history::record_image(&image));
+
+    let config = Config::get();
+    let bytes = encode_image(
+        &image,
+        encoding,
+        config.clipboard_image_max_size,
+        config.clipboard_image_quality,
+    )
+    .context("Failed to encode clipboard image")?;
+
+    success(&format!(
+        "Successfully served clipboard image as {} ({} bytes)",
+        encoding.mime(),
+        bytes.len()
+    ));
+    Ok(Response::from_data(encoding.mime(), bytes)
+        .with_additional_header("Vary", "Accept")
+        .with_additional_header(
+            "Content-Disposition",
+            format!("inline; filename=\"clipboard.{}\"", encoding.extension()),
+        ))
+}
+
+fn get_clipboard_all_handler(request: &Request) -> Result<Response> {
+    let priority = clipboard_priority(request)?;
+    let mut clipboard = Clipboard::new().context("Failed to initialize clipboard")?;
+
+    let mut representations: Vec<ClipboardContent> = Vec::new();
+    for kind in priority {
+        let has_files = representations
+            .iter()
+            .any(|content| matches!(content, ClipboardContent::Files(_)));
+        if kind == ClipboardKind::HtmlFiles && has_files {
+            continue;
         }
+        let record = representations.is_empty();
+        if let Some(content) = probe_clipboard(&mut clipboard, kind, record)? {
+            representations.push(content);
+        }
+    }
+
+    let msg = format!(
+        "Successfully served {} clipboard representation(s)",
+        representations.len()
+    );
+    success(&msg);
+    Ok(api::ok(msg, representations))
+}
+
+fn record_history(recorded: Result<()>) {
+    if let Err(error) = recorded {
+        warn!("Failed to record clipboard history: {:?}", error);
     }
+}
+
+fn get_clipboard_history_handler(_request: &Request) -> Result<Response> {
+    let entries = clipboard_history::entries();
+    Ok(api::ok(
+        format!("Found {} clipboard history entries", entries.len()),
+        entries,
+    ))
+}
 
-    if let Ok(html) = clipboard.get().html() {
-        let regex_for_file_urls = Regex::new(r#"src="(?P<path>file:///[^"]+)""#)?;
-        let mut encoded_file_paths = Vec::new();
-        for captures in regex_for_file_urls.captures_iter(&html) {
-            if let Some(path_match) = captures.name("path") {
-                let mut path_str = clean_path_string(path_match.as_str());
-                if path_str.starts_with("file:///") {
-                    path_str = &path_str[7..];
+fn restore_clipboard_history_handler(_request: &Request, id: u64) -> Result<Response> {
+    let entry = clipboard_history::restore(id).context("Failed to restore clipboard history")?;
+
+    let msg = format!(
+        "Successfully restored clipboard content: {}",
+        entry.preview()
+    );
+    success(&msg);
+    Ok(api::ok(msg, entry))
+}
+
+#[derive(Default)]
+struct ClipboardUpload {
+    kind: Option<String>,
+    data: Option<String>,
+    images: Vec<(String, Vec<u8>)>,
+    received_files: Vec<ReceivedFile>,
+}
+
+fn is_image_upload(file_name: &str, content_type: Option<&str>) -> bool {
+    content_type
+        .map(str::to_owned)
+        .or_else(|| {
+            mime_guess::from_path(file_name)
+                .first_raw()
+                .map(str::to_owned)
+        })
+        .is_some_and(|mime_type| mime_type.starts_with("image/"))
+}
+
+fn read_clipboard_upload(request: &Request) -> Result<ClipboardUpload> {
+    let sender = request_sender(request);
+    let mut guard = UploadGuard::new(content_length(request))?;
+    let mut upload = ClipboardUpload::default();
+    let mut multipart_data = match rouille::input::multipart::get_multipart_input(request) {
+        Ok(multipart_data) => multipart_data,
+        Err(MultipartError::WrongContentType) => {
+            let fields = rouille::input::post::raw_urlencoded_post_input(request)
+                .context("Failed to parse POST input")?;
+            for (name, value) in fields {
+                match name.as_str() {
+                    "type" => upload.kind = Some(value),
+                    "clipboard" => upload.data = Some(value),
+                    _ => {}
                 }
-                #[cfg(target_os = "windows")]
-                {
-                    if path_str.starts_with('/') {
-                        path_str = &path_str[1..];
+            }
+            return Ok(upload);
+        }
+        Err(error) => return Err(error).context("Failed to parse multipart input"),
+    };
+
+    while let Some(mut field) = multipart_data.next() {
+        match field.headers.filename.clone() {
+            Some(file_name) => {
+                let content_type = field
+                    .headers
+                    .content_type
+                    .as_ref()
+                    .map(|mime| mime.to_string());
+                let is_image = is_image_upload(&file_name, content_type.as_deref());
+                match upload.kind.as_deref() {
+                    None | Some("img") if is_image => {
+                        let mut bytes = Vec::new();
+                        let config = Config::get();
+                        let mut guarded_reader = guard
+                            .reader(&mut field.data, &config.path)
+                            .with_limit(MAX_CLIPBOARD_IMAGE_SIZE);
+                        if let Err(error) = guarded_reader.read_to_end(&mut bytes) {
+                            return Err(match guarded_reader.take_error() {
+                                Some(limit_error) => limit_error,
+                                None => anyhow::Error::new(error)
+                                    .context("Failed to receive the uploaded image"),
+                            });
+                        }
+                        upload.images.push((file_name, bytes));
+                    }
+                    // Files are only moved into place once the type turns out to be `file`.
+                    None | Some("file") => {
+                        let received_file = storage::receive_file(
+                            &mut field.data,
+                            &file_name,
+                            Some(&sender),
+                            &mut guard,
+                            None,
+                        )?;
+                        upload.received_files.push(received_file);
                     }
+                    _ => {}
+                }
+            }
+            None => {
+                let mut value = String::new();
+                field
+                    .data
+                    .read_to_string(&mut value)
+                    .context("Failed to read form field")?;
+                match &*field.headers.name {
+                    "type" => upload.kind = Some(value),
+                    "clipboard" => upload.data = Some(value),
+                    _ => {}
                 }
-                let encoded = base64_encode(path_str)
-                    .context(format!("Failed to encode path: {}", path_str))?;
-                encoded_file_paths.push(format!("{:?}", encoded));
             }
         }
+    }
+    Ok(upload)
+}
 
-        if !encoded_file_paths.is_empty() {
-            let paths_json_string = encoded_file_paths.join(",");
+fn resolve_clipboard_file(entry: &str) -> Result<PathBuf> {
+    base64_decode(entry)
+        .ok()
+        .and_then(|decoded| resolve_shared_path(&decoded))
+        .or_else(|| resolve_shared_path(entry))
+        .ok_or_else(|| {
+            ApiError::new(
+                ErrorCode::PathNotShared,
+                format!("Path is not inside a shared root: {:?}", entry),
+            )
+            .into()
+        })
+}
 
-            success(&format!(
-                "Successfully served clipboard content from HTML with {} file links",
-                encoded_file_paths.len()
-            ));
-            return Ok(Response::json(&format!(
-                r#"{{"success": true, "data": {{"type": "file", "data": [{}]}}}}"#,
-                paths_json_string
-            )));
-        }
-    }
+fn already_synced() -> Response {
+    let msg = "Clipboard is already up to date";
+    info!("\u{2570} Response: {}.", msg);
+    api::ok_empty(msg)
+}
 
-    if let Ok(text) = clipboard.get().text() {
-        success(&format!(
-            "Successfully served clipboard content as text: {:?}",
-            text
-        ));
-        return Ok(Response::json(&format!(
-            r#"{{"success": true, "data": {{"type": "text", "data": {:?}}}}}"#,
-            text
-        )));
+fn get_clipboard_watch_handler(request: &Request) -> Result<Response> {
+    if !clipboard_sync::is_enabled() {
+        return Err(ApiError::new(
+            ErrorCode::InvalidRequest,
+            "Clipboard sync is disabled, set 'clipboard sync = 1' in config.ini",
+        )
+        .into());
     }
+    let since = match request.get_param("since") {
+        Some(since) => since.parse::<u64>().map_err(|_| {
+            ApiError::new(
+                ErrorCode::InvalidRequest,
+                format!("Invalid sequence: {:?}", since),
+            )
+        })?,
+        None => 0,
+    };
+    let timeout = request
+        .get_param("timeout")
+        .and_then(|timeout| timeout.parse::<u64>().ok())
+        .unwrap_or(30)
+        .min(60);
 
-    anyhow::bail!("Unsupported clipboard format");
+    let change = clipboard_sync::wait_for_change(since, std::time::Duration::from_secs(timeout));
+    let msg = match &change {
+        Some(change) if change.sequence > since => {
+            format!("Clipboard changed, sync sequence {}", change.sequence)
+        }
+        Some(change) if change.sequence < since => {
+            format!(
+                "Clipboard sync restarted, sync sequence {}",
+                change.sequence
+            )
+        }
+        _ => "Clipboard unchanged".to_owned(),
+    };
+    info!("\u{2570} Response: {}.", msg);
+    Ok(api::ok(msg, change))
 }
 
 fn post_clipboard_handler(request: &Request) -> Result<Response> {
-    let post_data =
-        rouille::post_input!(request, {clipboard: String}).context("Failed to parse POST input")?;
-
-    let text_to_set = post_data.clipboard.as_str();
+    let mut upload = read_clipboard_upload(request)?;
+    let kind = match upload.kind.as_deref() {
+        Some(kind) => kind,
+        None if !upload.images.is_empty() => "img",
+        None if !upload.received_files.is_empty() => "file",
+        None => "text",
+    };
     let mut clipboard = Clipboard::new().context("Failed to initialize clipboard")?;
 
-    clipboard
-        .set_text(text_to_set)
-        .context("Failed to set clipboard contents")?;
+    let (detail, msg) = match kind {
+        "text" => {
+            let text = upload.data.as_deref().ok_or_else(|| {
+                ApiError::new(ErrorCode::InvalidRequest, "Missing clipboard text")
+            })?;
+            let hash = clipboard_sync::hash_content("text", text.as_bytes());
+            if clipboard_sync::is_current(&hash) {
+                return Ok(already_synced());
+            }
+            clipboard_sync::record_remote("text", hash);
+            clipboard
+                .set_text(text)
+                .context("Failed to set clipboard contents")?;
+            record_history(clipboard_history::record_text(text));
+            (
+                format!("Successfully set clipboard content with text: {:?}", text),
+                "Successfully set clipboard content as text".to_owned(),
+            )
+        }
+        "html" => {
+            let html = upload.data.as_deref().ok_or_else(|| {
+                ApiError::new(ErrorCode::InvalidRequest, "Missing clipboard HTML")
+            })?;
+            let hash = clipboard_sync::hash_content("html", html.as_bytes());
+            if clipboard_sync::is_current(&hash) {
+                return Ok(already_synced());
+            }
+            clipboard_sync::record_remote("html", hash);
+            clipboard
+                .set_html(html, None::<&str>)
+                .context("Failed to set clipboard HTML")?;
+            record_history(clipboard_history::record_html(html));
+            let msg = "Successfully set clipboard content as HTML".to_owned();
+            (msg.clone(), msg)
+        }
+        "img" => {
+            let bytes = match (upload.images.pop(), upload.data.as_deref()) {
+                (Some((_, bytes)), _) => bytes,
+                (None, Some(encoded)) => decode_base64_payload(encoded).map_err(|_| {
+                    ApiError::new(ErrorCode::InvalidRequest, "Invalid base64 image payload")
+                })?,
+                (None, None) => {
+                    return Err(ApiError::new(
+                        ErrorCode::InvalidRequest,
+                        "Missing clipboard image",
+                    )
+                    .into());
+                }
+            };
+            let image = decode_image(&bytes).map_err(|_| {
+                ApiError::new(
+                    ErrorCode::InvalidRequest,
+                    "Unsupported image format, convert HEIC images to PNG or JPEG before sending",
+                )
+            })?;
+            let hash = clipboard_sync::hash_content("img", &image.bytes);
+            if clipboard_sync::is_current(&hash) {
+                return Ok(already_synced());
+            }
+            clipboard_sync::record_remote("img", hash);
+            clipboard
+                .set_image(image.clone())
+                .context("Failed to set clipboard image")?;
+            record_history(clipboard_history::record_image(&image));
+            let msg = format!(
+                "Successfully set clipboard content as a {}\u{00D7}{} image",
+                image.width, image.height
+            );
+            (msg.clone(), msg)
+        }
+        "file" => {
+            let mut file_paths = Vec::new();
+            for received_file in upload.received_files {
+                file_paths.push(received_file.store()?.path);
+            }
+            for (file_name, bytes) in upload.images {
+                let sender = request_sender(request);
+                let mut guard = UploadGuard::new(Some(bytes.len() as u64))?;
+                let stored_file = storage::store_file(
+                    &mut bytes.as_slice(),
+                    &file_name,
+                    Some(&sender),
+                    &mut guard,
+                    None,
+                )?;
+                file_paths.push(stored_file.path);
+            }
+            if let Some(data) = upload.data.as_deref() {
+                for entry in data.lines().map(str::trim).filter(|line| !line.is_empty()) {
+                    file_paths.push(resolve_clipboard_file(entry)?);
+                }
+            }
+            if file_paths.is_empty() {
+                return Err(
+                    ApiError::new(ErrorCode::InvalidRequest, "Missing clipboard files").into(),
+                );
+            }
+            let path_strings: Vec<String> = file_paths
+                .iter()
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect();
+            let hash = clipboard_sync::hash_content("file", path_strings.join("\n").as_bytes());
+            if clipboard_sync::is_current(&hash) {
+                return Ok(already_synced());
+            }
+            clipboard_sync::record_remote("file", hash);
+            clipboard
+                .set()
+                .file_list(&file_paths)
+                .context("Failed to set clipboard file list")?;
+            let file_paths = path_strings;
+            record_history(clipboard_history::record_files(&file_paths));
+            let msg = format!(
+                "Successfully set clipboard content as a file list with {} items",
+                file_paths.len()
+            );
+            (msg.clone(), msg)
+        }
+        other => {
+            return Err(ApiError::new(
+                ErrorCode::InvalidRequest,
+                format!("Unsupported clipboard type: {:?}", other),
+            )
+            .into());
+        }
+    };
 
     thread::sleep(std::time::Duration::from_millis(200));
 
-    success(&format!(
-        "Successfully set clipboard content with text: {:?}",
-        text_to_set
-    ));
-    Ok(Response::json(&r#"{"success": true}"#))
+    success(&detail);
+    Ok(api::ok_empty(msg))
+}
+
+fn get_transfers_handler(_request: &Request) -> Result<Response> {
+    Ok(api::ok("Active transfers", transfers::list()))
+}
+
+fn get_history_handler(request: &Request) -> Result<Response> {
+    let direction = match request.get_param("direction").as_deref() {
+        Some("upload") => Some(Direction::Upload),
+        Some("download") => Some(Direction::Download),
+        Some(other) => {
+            return Err(ApiError::new(
+                ErrorCode::InvalidRequest,
+                format!("Invalid direction: {:?}, use upload or download", other),
+            )
+            .into());
+        }
+        None => None,
+    };
+    let limit = request
+        .get_param("limit")
+        .and_then(|limit| limit.parse::<usize>().ok())
+        .unwrap_or(50);
+    let query = request
+        .get_param("q")
+        .filter(|query| !query.trim().is_empty());
+
+    let entries = transfer_history::search(query.as_deref(), direction, limit);
+    Ok(api::ok(
+        format!("Found {} transfer(s)", entries.len()),
+        entries,
+    ))
+}
+
+fn get_info_handler(_request: &Request) -> Result<Response> {
+    let config = Config::get();
+    let info = ServerInfo {
+        name: config.name.clone(),
+        version: env!("CARGO_PKG_VERSION"),
+        api_versions: &["v1"],
+        os: std::env::consts::OS,
+        clipboard_types: &["text", "img", "file", "html"],
+        max_upload_size: [config.max_file_size, config.max_request_size]
+            .into_iter()
+            .filter(|limit| *limit > 0)
+            .min(),
+        https_port: config.https_port.filter(|_| tls::identity().is_some()),
+        auth: AuthInfo {
+            required: !config.allow_anonymous,
+            header: "ShortcutKey",
+            query: "key",
+        },
+    };
+    Ok(api::ok("Server information", info))
 }
 
 fn page_not_found_handler() -> Response {
-    Response::empty_404()
+    api::error(ErrorCode::NotFound, "Page not found")
+}
+
+fn route_request(request: &Request) -> Response {
+    router!(
+        request,
+        (GET) (/) => {
+            Response::text("Hello World!")
+        },
+        (GET) (/file/{path}) => {
+            get_file_handler(request, path).unwrap_or_else(failed)
+        },
+        (GET) (/archive/{paths}) => {
+            get_archive_handler(request, paths).unwrap_or_else(failed)
+        },
+        (POST) (/file) => {
+            post_file_handler(request).unwrap_or_else(failed)
+        },
+        (POST) (/upload) => {
+            post_upload_handler(request).unwrap_or_else(failed)
+        },
+        (GET) (/upload/{id: String}) => {
+            get_upload_handler(request, id).unwrap_or_else(failed)
+        },
+        (PUT) (/upload/{id: String}) => {
+            put_upload_handler(request, id).unwrap_or_else(failed)
+        },
+        (POST) (/upload/{id: String}/finalize) => {
+            finalize_upload_handler(request, id).unwrap_or_else(failed)
+        },
+        (GET) (/clipboard) => {
+            get_clipboard_handler(request).unwrap_or_else(failed)
+        },
+        (GET) (/clipboard/image) => {
+            get_clipboard_image_handler(request).unwrap_or_else(failed)
+        },
+        (GET) (/clipboard/all) => {
+            get_clipboard_all_handler(request).unwrap_or_else(failed)
+        },
+        (GET) (/clipboard/watch) => {
+            get_clipboard_watch_handler(request).unwrap_or_else(failed)
+        },
+        (GET) (/transfers) => {
+            get_transfers_handler(request).unwrap_or_else(failed)
+        },
+        (GET) (/history) => {
+            get_history_handler(request).unwrap_or_else(failed)
+        },
+        (GET) (/clipboard/history) => {
+            get_clipboard_history_handler(request).unwrap_or_else(failed)
+        },
+        (POST) (/clipboard/history/{id: u64}/restore) => {
+            restore_clipboard_history_handler(request, id).unwrap_or_else(failed)
+        },
+        (POST) (/clipboard) => {
+            post_clipboard_handler(request).unwrap_or_else(failed)
+        },
+        _ => {
+            page_not_found_handler()
+        }
+    )
+}
+
+fn handle_request(request: &Request) -> Response {
+    info!(
+        "\u{256D} Received request: {} {} from {:?}",
+        request.method(),
+        request.url(),
+        request.remote_addr()
+    );
+
+    if request.method() == "GET" && request.url() == "/api/v1/info" {
+        return get_info_handler(request).unwrap_or_else(failed);
+    }
+
+    if let Err(error) = authorize(request) {
+        return failed(error);
+    }
+
+    match request.remove_prefix("/api/v1") {
+        Some(request) => route_request(&request),
+        None => route_request(request),
+    }
+}
+
+fn stop_servers() {
+    let running_servers: Vec<_> = RUNNING_SERVERS.lock().unwrap().drain(..).collect();
+    for (handle, stop) in running_servers {
+        stop.send(()).ok();
+        handle.join().ok();
+    }
 }
 
 pub fn publish_server() -> Result<()> {
-    let port = Config::get().port;
-    let address = format!("0.0.0.0:{}", port);
+    stop_servers();
+    let config = Config::get();
+    let address = format!("0.0.0.0:{}", config.port);
 
     info!("\u{256D} Starting server on {}", address);
-    thread::spawn(move || {
-        rouille::start_server(address, move |request| {
-            info!(
-                "\u{256D} Received request: {} {} from {:?}",
-                request.method(),
-                request.url(),
-                request.remote_addr()
-            );
+    let server = rouille::Server::new(&address, handle_request)
+        .map_err(|error| anyhow::anyhow!(error))
+        .with_context(|| format!("Failed to bind server on {}", address))?;
+    RUNNING_SERVERS.lock().unwrap().push(server.stoppable());
 
-            router!(
-                request,
-                (GET) (/) => {
-                    Response::text("Hello World!")
-                },
-                (GET) (/file/{path}) => {
-                    get_file_handler(request, path).unwrap_or_else(failed)
-                },
-                (POST) (/file) => {
-                    post_file_handler(request).unwrap_or_else(failed)
-                },
-                (GET) (/clipboard) => {
-                    get_clipboard_handler(request).unwrap_or_else(failed)
-                },
-                (POST) (/clipboard) => {
-                    post_clipboard_handler(request).unwrap_or_else(failed)
-                },
-                _ => {
-                    page_not_found_handler()
-                }
-            )
-        });
-    });
+    if let (Some(https_port), Some(identity)) = (config.https_port, tls::identity()) {
+        let address = format!("0.0.0.0:{}", https_port);
+        info!("\u{2502} Starting HTTPS server on {}", address);
+        let server = rouille::Server::new_ssl(
+            &address,
+            handle_request,
+            identity.certificate.clone(),
+            identity.private_key.clone(),
+        )
+        .map_err(|error| anyhow::anyhow!(error))
+        .with_context(|| format!("Failed to bind HTTPS server on {}", address))?;
+        RUNNING_SERVERS.lock().unwrap().push(server.stoppable());
+    }
 
     info!("\u{2570} Server has been started successfully!");
     Ok(())
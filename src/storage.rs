@@ -0,0 +1,228 @@
+use crate::api::{ApiError, ErrorCode};
+use crate::config::{Config, ConflictPolicy};
+use crate::filename::sanitize_file_name;
+use crate::integrity::{self, HashingReader};
+use crate::limits::{self, Reservation, UploadGuard};
+use crate::routing;
+use crate::utils::{PartialFile, create_unique_file_path, sha256_file};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{create_dir_all, remove_file};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StoreStatus {
+    Stored,
+    Overwritten,
+    Skipped,
+    AlreadyPresent,
+    RolledBack,
+    Failed,
+}
+
+pub struct StoredFile {
+    pub name: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub sha256: String,
+    pub status: StoreStatus,
+}
+
+pub fn place_file(
+    destination_path: &Path,
+    file_name: &str,
+    size: u64,
+    sha256: &str,
+    move_into: impl FnOnce(&Path) -> Result<()>,
+) -> Result<StoredFile> {
+    let stored_file = place(destination_path, file_name, size, sha256, move_into)?;
+    if stored_file.status != StoreStatus::Skipped {
+        integrity::remember_digest(&stored_file.path, &stored_file.sha256);
+    }
+    Ok(stored_file)
+}
+
+fn place(
+    destination_path: &Path,
+    file_name: &str,
+    size: u64,
+    sha256: &str,
+    move_into: impl FnOnce(&Path) -> Result<()>,
+) -> Result<StoredFile> {
+    let config = Config::get();
+    let target_path = destination_path.join(file_name);
+
+    match config.on_conflict {
+        ConflictPolicy::Overwrite => {
+            create_dir_all(destination_path)?;
+            let existed = target_path.exists();
+            move_into(&target_path)?;
+            return Ok(StoredFile {
+                name: file_name.to_owned(),
+                path: target_path,
+                size,
+                sha256: sha256.to_owned(),
+                status: if existed {
+                    StoreStatus::Overwritten
+                } else {
+                    StoreStatus::Stored
+                },
+            });
+        }
+        ConflictPolicy::Skip if target_path.exists() => {
+            return Ok(StoredFile {
+                name: file_name.to_owned(),
+                path: target_path,
+                size,
+                sha256: sha256.to_owned(),
+                status: StoreStatus::Skipped,
+            });
+        }
+        ConflictPolicy::Dedupe if target_path.is_file() && sha256_file(&target_path)? == sha256 => {
+            return Ok(StoredFile {
+                name: file_name.to_owned(),
+                path: target_path,
+                size,
+                sha256: sha256.to_owned(),
+                status: StoreStatus::AlreadyPresent,
+            });
+        }
+        _ => {}
+    }
+
+    let unique_filepath =
+        create_unique_file_path(destination_path, file_name, &config.rename_template).context(
+            format!(
+                "Failed to create unique filepath in directory: {:?}",
+                destination_path
+            ),
+        )?;
+    if let Err(error) = move_into(&unique_filepath) {
+        remove_file(&unique_filepath).ok();
+        return Err(error);
+    }
+    Ok(StoredFile {
+        name: file_name.to_owned(),
+        path: unique_filepath,
+        size,
+        sha256: sha256.to_owned(),
+        status: StoreStatus::Stored,
+    })
+}
+
+// An upload that has been received and verified but not yet moved into place.
+pub struct ReceivedFile {
+    partial_file: PartialFile,
+    destination_path: PathBuf,
+    file_name: String,
+    size: u64,
+    sha256: String,
+    reservation: Reservation,
+}
+
+impl ReceivedFile {
+    pub fn store(self) -> Result<StoredFile> {
+        let Self {
+            partial_file,
+            destination_path,
+            file_name,
+            size,
+            sha256,
+            reservation,
+        } = self;
+        let stored_file = place_file(
+            &destination_path,
+            &file_name,
+            size,
+            &sha256,
+            |target_path| {
+                partial_file.persist(target_path).context(format!(
+                    "Failed to store the uploaded file at: {:?}",
+                    target_path
+                ))
+            },
+        )?;
+        if matches!(
+            stored_file.status,
+            StoreStatus::Stored | StoreStatus::Overwritten
+        ) {
+            reservation.commit();
+        }
+        Ok(stored_file)
+    }
+}
+
+pub fn receive_file<R: Read>(
+    reader: &mut R,
+    original_filename: &str,
+    sender: Option<&str>,
+    guard: &mut UploadGuard,
+    expected_hash: Option<&str>,
+) -> Result<ReceivedFile> {
+    let file_name = sanitize_file_name(original_filename)?;
+    let destination_path = routing::destination_dir(&file_name, sender);
+    guard.check_destination(&destination_path)?;
+
+    let mut partial_file = PartialFile::create(&destination_path).context(format!(
+        "Failed to create a partial file in directory: {:?}",
+        destination_path
+    ))?;
+    let mut hashing_reader = HashingReader::new(guard.reader(reader, &destination_path));
+    if let Err(error) = io::copy(&mut hashing_reader, partial_file.file()) {
+        if let Some(limit_error) = hashing_reader.inner_mut().take_error() {
+            return Err(limit_error);
+        }
+        if error.kind() == io::ErrorKind::StorageFull {
+            return Err(ApiError::new(
+                ErrorCode::InsufficientStorage,
+                "The computer ran out of free space while receiving the file",
+            )
+            .into());
+        }
+        if let Some(api_error) = error
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<ApiError>())
+        {
+            return Err(ApiError::new(api_error.code, api_error.to_string()).into());
+        }
+        return Err(error).context("Failed to receive the uploaded file content");
+    }
+    let size = hashing_reader.inner_mut().received();
+    let reservation = hashing_reader.inner_mut().take_reservation();
+    let sha256 = hashing_reader.finish();
+    integrity::verify(original_filename, expected_hash, &sha256)?;
+
+    Ok(ReceivedFile {
+        partial_file,
+        destination_path,
+        file_name,
+        size,
+        sha256,
+        reservation,
+    })
+}
+
+pub fn store_file<R: Read>(
+    reader: &mut R,
+    original_filename: &str,
+    sender: Option<&str>,
+    guard: &mut UploadGuard,
+    expected_hash: Option<&str>,
+) -> Result<StoredFile> {
+    receive_file(reader, original_filename, sender, guard, expected_hash)?.store()
+}
+
+// Undoes a store of this request. Rollback and overwrite are never configured
+// together, so there is no overwritten file to bring back.
+pub fn roll_back(stored_file: &mut StoredFile) -> Result<()> {
+    if stored_file.status == StoreStatus::Stored {
+        remove_file(&stored_file.path).with_context(|| {
+            format!("Failed to roll back uploaded file: {:?}", stored_file.path)
+        })?;
+        limits::give_back_usage(stored_file.size);
+        stored_file.status = StoreStatus::RolledBack;
+    }
+    Ok(())
+}
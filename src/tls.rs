@@ -0,0 +1,153 @@
+use crate::config::Config;
+use crate::utils::get_config_path;
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose;
+use log::info;
+use sha2::{Digest, Sha256};
+use std::fs::{read, write};
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+
+static IDENTITY: OnceLock<TlsIdentity> = OnceLock::new();
+
+pub struct TlsIdentity {
+    pub certificate: Vec<u8>,
+    pub private_key: Vec<u8>,
+    pub fingerprint: String,
+}
+
+fn pem_to_der(pem: &[u8]) -> Result<Vec<u8>> {
+    let pem = str::from_utf8(pem).context("Failed to decode certificate with UTF-8")?;
+    let body: String = pem
+        .lines()
+        .skip_while(|line| !line.starts_with("-----BEGIN CERTIFICATE-----"))
+        .skip(1)
+        .take_while(|line| !line.starts_with("-----END CERTIFICATE-----"))
+        .map(str::trim)
+        .collect();
+    general_purpose::STANDARD
+        .decode(body)
+        .context("Failed to decode certificate body")
+}
+
+fn fingerprint(der: &[u8]) -> String {
+    Sha256::digest(der)
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+// The private key is created readable by the current user only, not with the umask default.
+#[cfg(unix)]
+fn write_private_key(path: &Path, pem: &str) -> io::Result<()> {
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(pem.as_bytes())
+}
+
+#[cfg(not(unix))]
+fn write_private_key(path: &Path, pem: &str) -> io::Result<()> {
+    write(path, pem)
+}
+
+// Tightens a key left readable by others, e.g. one written by an older version.
+#[cfg(unix)]
+fn restrict_private_key(path: &Path) -> Result<()> {
+    use log::warn;
+    use std::fs::{Permissions, metadata, set_permissions};
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = metadata(path)
+        .with_context(|| format!("Failed to read permissions of {:?}", path))?
+        .permissions()
+        .mode();
+    if mode & 0o077 != 0 {
+        warn!(
+            "Private key {:?} was accessible by other users ({:o}), restricting it to 600.",
+            path,
+            mode & 0o777
+        );
+        set_permissions(path, Permissions::from_mode(0o600))
+            .with_context(|| format!("Failed to restrict permissions of {:?}", path))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn restrict_private_key(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+fn load_or_create_identity() -> Result<TlsIdentity> {
+    let config_path = get_config_path().context("Failed to get config path")?;
+    let certificate_path = config_path.with_file_name("cert.pem");
+    let private_key_path = config_path.with_file_name("key.pem");
+
+    if !certificate_path.exists() || !private_key_path.exists() {
+        info!(
+            "\u{25CF} Generating self-signed certificate at {:?}.",
+            certificate_path
+        );
+        let config = Config::get();
+        let host_name = config.name.trim_end_matches('.');
+        let host_name = host_name.strip_suffix(".local").unwrap_or(host_name);
+        let subject_alt_names = vec![
+            host_name.to_owned(),
+            format!("{}.local", host_name),
+            "localhost".to_owned(),
+        ];
+        let certified_key = rcgen::generate_simple_self_signed(subject_alt_names)
+            .context("Failed to generate self-signed certificate")?;
+        write(&certificate_path, certified_key.cert.pem())
+            .with_context(|| format!("Failed to write certificate into {:?}", certificate_path))?;
+        write_private_key(
+            &private_key_path,
+            &certified_key.signing_key.serialize_pem(),
+        )
+        .with_context(|| format!("Failed to write private key into {:?}", private_key_path))?;
+    }
+    restrict_private_key(&private_key_path)?;
+
+    let certificate = read(&certificate_path)
+        .with_context(|| format!("Failed to read certificate from {:?}", certificate_path))?;
+    let private_key = read(&private_key_path)
+        .with_context(|| format!("Failed to read private key from {:?}", private_key_path))?;
+    let fingerprint = fingerprint(&pem_to_der(&certificate)?);
+
+    Ok(TlsIdentity {
+        certificate,
+        private_key,
+        fingerprint,
+    })
+}
+
+pub fn init() -> Result<()> {
+    if Config::get().https_port.is_none() || IDENTITY.get().is_some() {
+        return Ok(());
+    }
+
+    info!("\u{256D} Loading TLS certificate.");
+    let identity = load_or_create_identity()?;
+    info!(
+        "\u{2570} TLS certificate loaded with SHA-256 fingerprint {}.",
+        identity.fingerprint
+    );
+    IDENTITY
+        .set(identity)
+        .map_err(|_| anyhow::anyhow!("TLS identity already initialized"))
+}
+
+pub fn identity() -> Option<&'static TlsIdentity> {
+    IDENTITY.get()
+}
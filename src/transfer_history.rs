@@ -0,0 +1,202 @@
+use crate::api::{ApiError, ErrorCode};
+use crate::config::Config;
+use crate::storage::StoreStatus;
+use crate::transfers::Direction;
+use crate::tray;
+use crate::utils::get_config_dir;
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs::{read_to_string, write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const HISTORY_FILE_NAME: &str = "transfer_history.json";
+
+static HISTORY: Mutex<Option<Vec<HistoryEntry>>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Completed,
+    Partial,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Peer {
+    pub ip: String,
+    pub device: Option<String>,
+}
+
+impl Peer {
+    pub fn name(&self) -> &str {
+        self.device.as_deref().unwrap_or(&self.ip)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HistoryFile {
+    pub name: String,
+    pub path: Option<PathBuf>,
+    pub size: Option<u64>,
+    pub status: Option<StoreStatus>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: u64,
+    pub timestamp: u64,
+    pub direction: Direction,
+    pub peer: Peer,
+    pub files: Vec<HistoryFile>,
+    pub outcome: Outcome,
+}
+
+impl HistoryEntry {
+    pub fn preview(&self) -> String {
+        let arrow = match self.direction {
+            Direction::Upload => '\u{2193}',
+            Direction::Download => '\u{2191}',
+        };
+        let name = match self.files.as_slice() {
+            [] => "request".to_owned(),
+            [file] => file.name.clone(),
+            [file, rest @ ..] => format!("{} (+{})", file.name, rest.len()),
+        };
+        let preposition = match self.direction {
+            Direction::Upload => "from",
+            Direction::Download => "to",
+        };
+        let outcome = match self.outcome {
+            Outcome::Completed => "",
+            Outcome::Partial => " (partial)",
+            Outcome::Failed => " (failed)",
+            Outcome::Cancelled => " (cancelled)",
+        };
+        format!(
+            "{} {} {} {}{}",
+            arrow,
+            name,
+            preposition,
+            self.peer.name(),
+            outcome
+        )
+    }
+
+    fn matches(&self, query: &str) -> bool {
+        self.peer.name().to_lowercase().contains(query)
+            || self.peer.ip.contains(query)
+            || self
+                .files
+                .iter()
+                .any(|file| file.name.to_lowercase().contains(query))
+    }
+}
+
+fn history_path() -> Result<PathBuf> {
+    Ok(get_config_dir()?.join(HISTORY_FILE_NAME))
+}
+
+fn load() -> Vec<HistoryEntry> {
+    let loaded = history_path().and_then(|path| {
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let json = read_to_string(&path)
+            .with_context(|| format!("Failed to read transfer history from {:?}", path))?;
+        serde_json::from_str(&json).context("Failed to parse transfer history")
+    });
+    loaded.unwrap_or_else(|error| {
+        warn!("Failed to load transfer history: {:?}", error);
+        Vec::new()
+    })
+}
+
+fn save(entries: &[HistoryEntry]) -> Result<()> {
+    let path = history_path()?;
+    let json = serde_json::to_string(entries).context("Failed to serialize transfer history")?;
+    write(&path, json).with_context(|| format!("Failed to write transfer history to {:?}", path))
+}
+
+fn with_history<T>(action: impl FnOnce(&mut Vec<HistoryEntry>) -> T) -> T {
+    let mut history = HISTORY.lock().unwrap();
+    action(history.get_or_insert_with(load))
+}
+
+pub fn record(direction: Direction, peer: Peer, files: Vec<HistoryFile>, outcome: Outcome) {
+    let capacity = Config::get().transfer_history_size;
+    // A failed request is worth keeping even when it was rejected before any file arrived.
+    if capacity == 0 || (files.is_empty() && outcome == Outcome::Completed) {
+        return;
+    }
+
+    let saved = with_history(|entries| {
+        let entry = HistoryEntry {
+            id: entries.first().map_or(1, |entry| entry.id + 1),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            direction,
+            peer,
+            files,
+            outcome,
+        };
+        entries.insert(0, entry);
+        entries.truncate(capacity);
+        save(entries)
+    });
+
+    match saved {
+        Ok(()) => tray::refresh_menu(),
+        Err(error) => warn!("Failed to record transfer history: {:?}", error),
+    }
+}
+
+pub fn search(
+    query: Option<&str>,
+    direction: Option<Direction>,
+    limit: usize,
+) -> Vec<HistoryEntry> {
+    let query = query.map(|query| query.trim().to_lowercase());
+    with_history(|entries| {
+        entries
+            .iter()
+            .filter(|entry| direction.is_none_or(|direction| entry.direction == direction))
+            .filter(|entry| query.as_deref().is_none_or(|query| entry.matches(query)))
+            .take(limit)
+            .cloned()
+            .collect()
+    })
+}
+
+pub fn reveal(id: u64) -> Result<()> {
+    let entry = with_history(|entries| entries.iter().find(|entry| entry.id == id).cloned())
+        .ok_or_else(|| {
+            ApiError::new(
+                ErrorCode::NotFound,
+                format!("Unknown transfer history entry: {}", id),
+            )
+        })?;
+    let path = entry
+        .files
+        .iter()
+        .filter_map(|file| file.path.as_ref())
+        .find(|path| path.exists())
+        .ok_or_else(|| {
+            ApiError::new(
+                ErrorCode::NotFound,
+                format!("The files of transfer {} no longer exist", id),
+            )
+        })?;
+
+    opener::reveal(path).with_context(|| format!("Failed to reveal {:?}", path))?;
+    info!(
+        "\u{25CF} Revealed transfer history entry {}: {:?}.",
+        id, path
+    );
+    Ok(())
+}
@@ -0,0 +1,222 @@
+use crate::api::{ApiError, ErrorCode};
+use crate::transfer_history::{self, HistoryFile, Outcome, Peer};
+use crate::tray;
+use crate::utils::format_size;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+// Progress is pushed to the tray at most this often.
+const REFRESH_INTERVAL_MILLIS: u64 = 500;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+static TRANSFERS: Mutex<Vec<Weak<Transfer>>> = Mutex::new(Vec::new());
+static LAST_REFRESH: Mutex<Option<Instant>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Upload,
+    Download,
+}
+
+pub struct Transfer {
+    id: u64,
+    direction: Direction,
+    name: String,
+    peer: Peer,
+    paths: Vec<PathBuf>,
+    total: Option<u64>,
+    transferred: AtomicU64,
+    cancelled: AtomicBool,
+    finished: AtomicBool,
+    started: SystemTime,
+}
+
+#[derive(Serialize)]
+pub struct TransferInfo {
+    pub id: u64,
+    pub direction: Direction,
+    pub name: String,
+    pub peer: String,
+    pub transferred: u64,
+    pub total: Option<u64>,
+    pub started: u64,
+}
+
+impl TransferInfo {
+    pub fn describe(&self) -> String {
+        let arrow = match self.direction {
+            Direction::Upload => '\u{2193}',
+            Direction::Download => '\u{2191}',
+        };
+        match self.total.filter(|total| *total > 0) {
+            Some(total) => format!(
+                "{} {} {}% ({} of {})",
+                arrow,
+                self.name,
+                (self.transferred.min(total) * 100 / total),
+                format_size(self.transferred),
+                format_size(total)
+            ),
+            None => format!("{} {} {}", arrow, self.name, format_size(self.transferred)),
+        }
+    }
+}
+
+impl Transfer {
+    pub fn reader<R: Read>(self: &Arc<Self>, inner: R) -> ProgressReader<R> {
+        ProgressReader {
+            inner,
+            transfer: self.clone(),
+        }
+    }
+
+    fn info(&self) -> TransferInfo {
+        TransferInfo {
+            id: self.id,
+            direction: self.direction,
+            name: self.name.clone(),
+            peer: self.peer.name().to_owned(),
+            transferred: self.transferred.load(Ordering::Relaxed),
+            total: self.total,
+            started: self
+                .started
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        }
+    }
+}
+
+impl Drop for Transfer {
+    fn drop(&mut self) {
+        refresh_tray(true);
+        // Uploads are recorded by their handler, which knows where each file ended up.
+        if self.direction != Direction::Download {
+            return;
+        }
+        let transferred = *self.transferred.get_mut();
+        let outcome = if *self.cancelled.get_mut() {
+            Outcome::Cancelled
+        } else if *self.finished.get_mut() {
+            Outcome::Completed
+        } else if transferred > 0 {
+            Outcome::Failed
+        } else {
+            return;
+        };
+        // A single path is served as one file or archive, so its size is what was sent.
+        let size = match self.paths.len() {
+            1 if *self.finished.get_mut() => Some(transferred),
+            1 => self.total,
+            _ => None,
+        };
+        let files = self
+            .paths
+            .iter()
+            .map(|path| HistoryFile {
+                name: path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| path.to_string_lossy().into_owned()),
+                path: Some(path.clone()),
+                size,
+                status: None,
+            })
+            .collect();
+        transfer_history::record(self.direction, self.peer.clone(), files, outcome);
+    }
+}
+
+pub struct ProgressReader<R> {
+    inner: R,
+    transfer: Arc<Transfer>,
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.transfer.cancelled.load(Ordering::Relaxed) {
+            return Err(io::Error::other(ApiError::new(
+                ErrorCode::Cancelled,
+                "The transfer was cancelled on the computer",
+            )));
+        }
+        let read = self.inner.read(buf)?;
+        if read == 0 && !buf.is_empty() {
+            self.transfer.finished.store(true, Ordering::Relaxed);
+        }
+        self.transfer
+            .transferred
+            .fetch_add(read as u64, Ordering::Relaxed);
+        refresh_tray(false);
+        Ok(read)
+    }
+}
+
+fn refresh_tray(force: bool) {
+    let mut last_refresh = LAST_REFRESH.lock().unwrap();
+    let due = last_refresh
+        .is_none_or(|last| last.elapsed().as_millis() >= REFRESH_INTERVAL_MILLIS as u128);
+    if force || due {
+        *last_refresh = Some(Instant::now());
+        tray::refresh_transfers();
+    }
+}
+
+pub fn start(
+    direction: Direction,
+    name: &str,
+    peer: Peer,
+    paths: Vec<PathBuf>,
+    total: Option<u64>,
+) -> Arc<Transfer> {
+    let transfer = Arc::new(Transfer {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        direction,
+        name: name.to_owned(),
+        peer,
+        paths,
+        total,
+        transferred: AtomicU64::new(0),
+        cancelled: AtomicBool::new(false),
+        finished: AtomicBool::new(false),
+        started: SystemTime::now(),
+    });
+    let mut transfers = TRANSFERS.lock().unwrap();
+    transfers.retain(|transfer| transfer.strong_count() > 0);
+    transfers.push(Arc::downgrade(&transfer));
+    drop(transfers);
+
+    refresh_tray(true);
+    transfer
+}
+
+pub fn list() -> Vec<TransferInfo> {
+    let mut transfers = TRANSFERS.lock().unwrap();
+    transfers.retain(|transfer| transfer.strong_count() > 0);
+    transfers
+        .iter()
+        .filter_map(Weak::upgrade)
+        .map(|transfer| transfer.info())
+        .collect()
+}
+
+pub fn cancel(id: u64) -> bool {
+    let transfer = TRANSFERS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .find(|transfer| transfer.id == id);
+    match transfer {
+        Some(transfer) => {
+            transfer.cancelled.store(true, Ordering::Relaxed);
+            true
+        }
+        None => false,
+    }
+}
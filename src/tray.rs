@@ -1,28 +1,102 @@
+use crate::clipboard_history;
+use crate::tls;
+use crate::transfer_history;
+use crate::transfers;
 use crate::utils::get_config_path;
 use anyhow::{Context, Result};
+use arboard::Clipboard;
 use image::ImageFormat;
 use log::{error, info};
+use std::collections::HashMap;
 use std::rc::Rc;
+use std::sync::Mutex;
 use tao::{
     event::Event,
-    event_loop::{ControlFlow, EventLoopBuilder},
+    event_loop::{ControlFlow, EventLoopBuilder, EventLoopProxy},
 };
 use tray_icon::{
-    Icon, TrayIconBuilder, TrayIconEvent,
+    Icon, TrayIcon, TrayIconBuilder, TrayIconEvent,
     menu::MenuEvent,
-    menu::{Menu, MenuItem},
+    menu::{Menu, MenuId, MenuItem, Submenu},
 };
 
+const HISTORY_MENU_LENGTH: usize = 10;
+
+static EVENT_PROXY: Mutex<Option<EventLoopProxy<UserEvent>>> = Mutex::new(None);
+
 #[derive(Debug)]
 enum UserEvent {
     TrayIconEvent(TrayIconEvent),
     MenuEvent(MenuEvent),
+    RefreshMenu,
+    RefreshTransfers,
+}
+
+pub fn refresh_menu() {
+    if let Some(proxy) = EVENT_PROXY.lock().unwrap().as_ref() {
+        proxy.send_event(UserEvent::RefreshMenu).ok();
+    }
+}
+
+pub fn refresh_transfers() {
+    if let Some(proxy) = EVENT_PROXY.lock().unwrap().as_ref() {
+        proxy.send_event(UserEvent::RefreshTransfers).ok();
+    }
+}
+
+fn rebuild_transfers_menu(transfers_menu: &Submenu, tray_icon: &TrayIcon) -> HashMap<MenuId, u64> {
+    while transfers_menu.remove_at(0).is_some() {}
+
+    let transfers = transfers::list();
+    let mut transfer_items = HashMap::new();
+    for transfer in &transfers {
+        let item = MenuItem::new(format!("Cancel {}", transfer.describe()), true, None);
+        transfers_menu.append(&item).ok();
+        transfer_items.insert(item.id().clone(), transfer.id);
+    }
+    transfers_menu.set_enabled(!transfer_items.is_empty());
+
+    let tooltip = match transfers.as_slice() {
+        [] => "AirDropPro".to_owned(),
+        [transfer] => format!("AirDropPro\n{}", transfer.describe()),
+        transfers => format!("AirDropPro\n{} active transfers", transfers.len()),
+    };
+    tray_icon.set_tooltip(Some(tooltip)).ok();
+    transfer_items
+}
+
+fn rebuild_recent_menu(recent_menu: &Submenu) -> HashMap<MenuId, u64> {
+    while recent_menu.remove_at(0).is_some() {}
+
+    let mut recent_items = HashMap::new();
+    for entry in transfer_history::search(None, None, HISTORY_MENU_LENGTH) {
+        let item = MenuItem::new(entry.preview(), true, None);
+        recent_menu.append(&item).ok();
+        recent_items.insert(item.id().clone(), entry.id);
+    }
+    recent_menu.set_enabled(!recent_items.is_empty());
+    recent_items
+}
+
+fn rebuild_history_menu(history_menu: &Submenu) -> HashMap<MenuId, u64> {
+    while history_menu.remove_at(0).is_some() {}
+
+    let entries = clipboard_history::entries();
+    let mut history_items = HashMap::new();
+    for entry in entries.iter().take(HISTORY_MENU_LENGTH) {
+        let item = MenuItem::new(entry.preview(), true, None);
+        history_menu.append(&item).ok();
+        history_items.insert(item.id().clone(), entry.id);
+    }
+    history_menu.set_enabled(!history_items.is_empty());
+    history_items
 }
 
 pub fn start_gui_tray() -> Result<()> {
     let event_loop = EventLoopBuilder::<UserEvent>::with_user_event().build();
     let proxy_tray = event_loop.create_proxy();
     let proxy_menu = event_loop.create_proxy();
+    *EVENT_PROXY.lock().unwrap() = Some(event_loop.create_proxy());
 
     TrayIconEvent::set_event_handler(Some(move |event| {
         proxy_tray.send_event(UserEvent::TrayIconEvent(event)).ok();
@@ -41,13 +115,37 @@ pub fn start_gui_tray() -> Result<()> {
 
     let tray_menu = Menu::new();
     let open_item = Rc::new(MenuItem::new("Open Config File", true, None));
+    let history_menu = Submenu::new("Clipboard History", true);
+    let mut history_items = rebuild_history_menu(&history_menu);
+    let recent_menu = Submenu::new("Recent Transfers", true);
+    let mut recent_items = rebuild_recent_menu(&recent_menu);
+    let transfers_menu = Submenu::new("Transfers", false);
+    let mut transfer_items = HashMap::new();
+    let fingerprint_item = tls::identity().map(|identity| {
+        let short_fingerprint = identity
+            .fingerprint
+            .get(..23)
+            .unwrap_or(&identity.fingerprint);
+        Rc::new(MenuItem::new(
+            format!("Copy TLS Fingerprint ({}\u{2026})", short_fingerprint),
+            true,
+            None,
+        ))
+    });
     let quit_item = Rc::new(MenuItem::new("Quit", true, None));
     tray_menu.append(&*open_item).ok();
+    tray_menu.append(&history_menu).ok();
+    tray_menu.append(&recent_menu).ok();
+    tray_menu.append(&transfers_menu).ok();
+    if let Some(fingerprint_item) = &fingerprint_item {
+        tray_menu.append(&**fingerprint_item).ok();
+    }
     tray_menu.append(&*quit_item).ok();
 
-    let _tray_icon = TrayIconBuilder::new()
+    let tray_icon = TrayIconBuilder::new()
         .with_menu(Box::new(tray_menu))
         .with_icon(icon)
+        .with_tooltip("AirDropPro")
         .build()
         .context("Failed to build tray icon.")?;
 
@@ -58,8 +156,30 @@ pub fn start_gui_tray() -> Result<()> {
                 UserEvent::TrayIconEvent(tray_event) => {
                     info!("\u{25CF} Received tray icon event: {:?}", tray_event);
                 }
+                UserEvent::RefreshMenu => {
+                    history_items = rebuild_history_menu(&history_menu);
+                    recent_items = rebuild_recent_menu(&recent_menu);
+                }
+                UserEvent::RefreshTransfers => {
+                    transfer_items = rebuild_transfers_menu(&transfers_menu, &tray_icon);
+                }
                 UserEvent::MenuEvent(menu_event) => {
                     info!("\u{25CF} Received menu event: {:?}", menu_event);
+                    if let Some(entry_id) = history_items.get(&menu_event.id)
+                        && let Err(error) = clipboard_history::restore(*entry_id)
+                    {
+                        error!("Failed to restore clipboard history entry: {:?}", error);
+                    }
+                    if let Some(entry_id) = recent_items.get(&menu_event.id)
+                        && let Err(error) = transfer_history::reveal(*entry_id)
+                    {
+                        error!("Failed to reveal transfer history entry: {:?}", error);
+                    }
+                    if let Some(transfer_id) = transfer_items.get(&menu_event.id)
+                        && transfers::cancel(*transfer_id)
+                    {
+                        info!("\u{25CF} Cancelled transfer {} from the tray.", transfer_id);
+                    }
                     if menu_event.id == open_item.id() {
                         if let Ok(config_path) = get_config_path() {
                             if let Err(error) = opener::open(config_path) {
@@ -69,6 +189,16 @@ pub fn start_gui_tray() -> Result<()> {
                             error!("Failed to get config path.");
                         }
                     }
+                    if let (Some(fingerprint_item), Some(identity)) =
+                        (&fingerprint_item, tls::identity())
+                        && menu_event.id == fingerprint_item.id()
+                    {
+                        let copied = Clipboard::new()
+                            .and_then(|mut clipboard| clipboard.set_text(&identity.fingerprint));
+                        if let Err(error) = copied {
+                            error!("Failed to copy TLS fingerprint: {}", error);
+                        }
+                    }
                     if menu_event.id == quit_item.id() {
                         *control_flow = ControlFlow::Exit;
                     }
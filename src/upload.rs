@@ -0,0 +1,468 @@
+use crate::api::{ApiError, ErrorCode};
+use crate::config::Config;
+use crate::filename::sanitize_file_name;
+use crate::integrity;
+use crate::limits;
+use crate::routing;
+use crate::storage::{self, StoreStatus, StoredFile};
+use crate::utils::{move_file, sha256_file};
+use anyhow::{Context, Result, ensure};
+use configparser::ini::Ini;
+use log::{info, warn};
+use serde::Serialize;
+use std::fs::{File, OpenOptions, create_dir_all, read_dir, remove_file};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SESSION_DIR_NAME: &str = ".airdroppro-uploads";
+const COLLECT_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+static SESSION_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Serialize)]
+pub struct UploadSession {
+    pub id: String,
+    pub name: String,
+    pub size: u64,
+    pub hash: Option<String>,
+    pub received: Vec<(u64, u64)>,
+    pub finalizing: bool,
+}
+
+impl UploadSession {
+    pub fn is_complete(&self) -> bool {
+        self.size == 0 || self.received == [(0, self.size)]
+    }
+
+    pub fn received_string(&self) -> String {
+        self.received
+            .iter()
+            .map(|(start, end)| format!("{}-{}", start, end))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    fn insert_range(&mut self, start: u64, end: u64) {
+        if start == end {
+            return;
+        }
+        self.received.push((start, end));
+        self.received.sort_unstable();
+
+        let mut merged: Vec<(u64, u64)> = Vec::with_capacity(self.received.len());
+        for &(start, end) in &self.received {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        self.received = merged;
+    }
+
+    fn load(id: &str) -> Result<Self> {
+        ensure!(
+            !id.is_empty() && id.chars().all(|c| c.is_ascii_hexdigit()),
+            ApiError::new(
+                ErrorCode::InvalidRequest,
+                format!("Invalid upload session id: {:?}", id)
+            )
+        );
+        let meta_path = session_dir().join(format!("{}.ini", id));
+        if !meta_path.exists() {
+            return Err(ApiError::new(
+                ErrorCode::NotFound,
+                format!("Unknown upload session: {}", id),
+            )
+            .into());
+        }
+
+        let mut ini = Ini::new();
+        ini.set_inline_comment_symbols(Some(&[]));
+        ini.load(&meta_path)
+            .map_err(|error| anyhow::anyhow!(error))
+            .with_context(|| format!("Failed to load upload session from {:?}", meta_path))?;
+
+        let name = ini
+            .get("Session", "name")
+            .context("Upload session missing 'name'")?;
+        let size = ini
+            .get("Session", "size")
+            .context("Upload session missing 'size'")?
+            .parse::<u64>()
+            .context("Failed to parse upload session 'size'")?;
+        let hash = ini.get("Session", "hash").filter(|hash| !hash.is_empty());
+        let mut received = Vec::new();
+        for range in ini
+            .get("Session", "received")
+            .unwrap_or_default()
+            .split(',')
+            .filter(|range| !range.is_empty())
+        {
+            let (start, end) = range
+                .split_once('-')
+                .context("Failed to parse upload session range")?;
+            received.push((start.parse::<u64>()?, end.parse::<u64>()?));
+        }
+
+        let finalizing = ini.get("Session", "finalizing").as_deref() == Some("1");
+
+        Ok(Self {
+            id: id.to_owned(),
+            name,
+            size,
+            hash,
+            received,
+            finalizing,
+        })
+    }
+
+    fn save(&self) -> Result<()> {
+        let mut ini = Ini::new();
+        ini.set("Session", "name", Some(self.name.clone()));
+        ini.set("Session", "size", Some(self.size.to_string()));
+        ini.set("Session", "hash", self.hash.clone());
+        ini.set("Session", "received", Some(self.received_string()));
+        ini.set(
+            "Session",
+            "finalizing",
+            Some(if self.finalizing { "1" } else { "0" }.to_owned()),
+        );
+        ini.write(self.meta_path())
+            .with_context(|| format!("Failed to save upload session {}", self.id))
+    }
+
+    fn meta_path(&self) -> PathBuf {
+        session_dir().join(format!("{}.ini", self.id))
+    }
+
+    fn data_path(&self) -> PathBuf {
+        session_dir().join(format!("{}.part", self.id))
+    }
+}
+
+fn session_dir() -> PathBuf {
+    Config::get().path.join(SESSION_DIR_NAME)
+}
+
+fn generate_session_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    format!(
+        "{:x}{:04x}",
+        nanos,
+        COUNTER.fetch_add(1, Ordering::Relaxed) & 0xffff
+    )
+}
+
+pub fn create_session(name: &str, size: u64, hash: Option<String>) -> Result<UploadSession> {
+    let name = sanitize_file_name(name)?;
+    let hash = hash
+        .filter(|hash| !hash.trim().is_empty())
+        .map(|hash| integrity::parse_expected_hash(&hash))
+        .transpose()?;
+    limits::check_file_size(size)?;
+    limits::check_quota(size)?;
+    create_dir_all(session_dir())
+        .with_context(|| format!("Failed to create session directory {:?}", session_dir()))?;
+    limits::check_free_space(&session_dir(), size)?;
+
+    let _guard = SESSION_LOCK.lock().unwrap();
+    let session = UploadSession {
+        id: generate_session_id(),
+        name,
+        size,
+        hash,
+        received: Vec::new(),
+        finalizing: false,
+    };
+    let data_file = File::create(session.data_path())
+        .with_context(|| format!("Failed to create upload data for {}", session.id))?;
+    data_file
+        .set_len(size)
+        .with_context(|| format!("Failed to allocate upload data for {}", session.id))?;
+    session.save()?;
+
+    info!(
+        "\u{25CF} Created upload session {} for {:?} ({} bytes).",
+        session.id, session.name, session.size
+    );
+    Ok(session)
+}
+
+pub fn get_session(id: &str) -> Result<UploadSession> {
+    let _guard = SESSION_LOCK.lock().unwrap();
+    UploadSession::load(id)
+}
+
+pub fn write_chunk<R: Read>(id: &str, offset: u64, chunk: R) -> Result<UploadSession> {
+    let session = get_session(id)?;
+    ensure_not_finalizing(&session)?;
+    ensure!(
+        offset <= session.size,
+        ApiError::new(
+            ErrorCode::RangeNotSatisfiable,
+            format!(
+                "Offset {} is beyond the upload size {}",
+                offset, session.size
+            )
+        )
+    );
+
+    let mut data_file = OpenOptions::new()
+        .write(true)
+        .open(session.data_path())
+        .with_context(|| format!("Failed to open upload data for {}", id))?;
+    data_file
+        .seek(SeekFrom::Start(offset))
+        .with_context(|| format!("Failed to seek upload data for {}", id))?;
+    let written = io::copy(&mut chunk.take(session.size - offset), &mut data_file)
+        .with_context(|| format!("Failed to receive chunk for upload session {}", id))?;
+
+    let _guard = SESSION_LOCK.lock().unwrap();
+    let mut session = UploadSession::load(id)?;
+    session.insert_range(offset, offset + written);
+    session.save()?;
+    Ok(session)
+}
+
+fn ensure_not_finalizing(session: &UploadSession) -> Result<()> {
+    ensure!(
+        !session.finalizing,
+        ApiError::new(
+            ErrorCode::UploadIncomplete,
+            format!("Upload session {} is already being finalized", session.id)
+        )
+    );
+    Ok(())
+}
+
+// Hashing and moving a large file can take a while, so only the checks hold the lock
+// and the `finalizing` flag keeps other requests off the session meanwhile.
+pub fn finalize_session(id: &str, sender: Option<&str>) -> Result<StoredFile> {
+    let session = {
+        let _guard = SESSION_LOCK.lock().unwrap();
+        let mut session = UploadSession::load(id)?;
+        ensure_not_finalizing(&session)?;
+        ensure!(
+            session.is_complete(),
+            ApiError::new(
+                ErrorCode::UploadIncomplete,
+                format!(
+                    "Upload session {} is incomplete, received: {}",
+                    id,
+                    session.received_string()
+                )
+            )
+        );
+        session.finalizing = true;
+        session.save()?;
+        session
+    };
+
+    let stored_file = store_session(&session, sender);
+    match &stored_file {
+        Err(error)
+            if error
+                .downcast_ref::<ApiError>()
+                .is_some_and(|error| error.code == ErrorCode::HashMismatch) =>
+        {
+            remove_session(&session);
+        }
+        Err(_) => {
+            let _guard = SESSION_LOCK.lock().unwrap();
+            let mut session = UploadSession::load(id)?;
+            session.finalizing = false;
+            session.save()?;
+        }
+        Ok(_) => remove_session(&session),
+    }
+    stored_file
+}
+
+fn store_session(session: &UploadSession, sender: Option<&str>) -> Result<StoredFile> {
+    let sha256 = sha256_file(session.data_path())?;
+    integrity::verify(&session.name, session.hash.as_deref(), &sha256)?;
+
+    let reservation = limits::reserve(session.size)?;
+    let data_path = session.data_path();
+    let destination_path = routing::destination_dir(&session.name, sender);
+    let stored_file = storage::place_file(
+        &destination_path,
+        &session.name,
+        session.size,
+        &sha256,
+        |unique_filepath| {
+            move_file(&data_path, unique_filepath).with_context(|| {
+                format!(
+                    "Failed to move upload session {} to {:?}",
+                    session.id, unique_filepath
+                )
+            })
+        },
+    )?;
+    if matches!(
+        stored_file.status,
+        StoreStatus::Stored | StoreStatus::Overwritten
+    ) {
+        reservation.commit();
+    }
+    Ok(stored_file)
+}
+
+fn remove_session(session: &UploadSession) {
+    remove_file(session.data_path()).ok();
+    remove_file(session.meta_path()).ok();
+}
+
+fn collect_stale_sessions() -> Result<()> {
+    let dir = session_dir();
+    if !dir.exists() {
+        return Ok(());
+    }
+
+    let timeout = Config::get().upload_session_timeout;
+    let _guard = SESSION_LOCK.lock().unwrap();
+    for entry in read_dir(&dir).with_context(|| format!("Failed to read {:?}", dir))? {
+        let path = entry?.path();
+        // The metadata file is saved on every chunk and when finalizing starts, so it
+        // tells whether the session is in use even while an old `.part` is being hashed.
+        let meta_path = path.with_extension("ini");
+        let activity_path = if meta_path.exists() {
+            &meta_path
+        } else {
+            &path
+        };
+        let is_stale = activity_path
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_some_and(|elapsed| elapsed > timeout);
+        if is_stale {
+            info!("\u{25CF} Removing stale upload data {:?}.", path);
+            remove_file(&path).ok();
+        }
+    }
+    Ok(())
+}
+
+// A finalize cut short by a restart would otherwise block the session until it expires.
+fn reset_interrupted_finalizes() -> Result<()> {
+    let dir = session_dir();
+    if !dir.exists() {
+        return Ok(());
+    }
+
+    let _guard = SESSION_LOCK.lock().unwrap();
+    for entry in read_dir(&dir).with_context(|| format!("Failed to read {:?}", dir))? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|extension| extension != "ini") {
+            continue;
+        }
+        let Some(id) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        let mut session = UploadSession::load(id)?;
+        if session.finalizing {
+            session.finalizing = false;
+            session.save()?;
+        }
+    }
+    Ok(())
+}
+
+pub fn spawn_session_collector() {
+    if let Err(error) = reset_interrupted_finalizes() {
+        warn!("Failed to reset interrupted upload sessions: {:?}", error);
+    }
+    thread::spawn(|| {
+        loop {
+            if let Err(error) = collect_stale_sessions() {
+                warn!("Failed to collect stale upload sessions: {:?}", error);
+            }
+            thread::sleep(COLLECT_INTERVAL);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(size: u64) -> UploadSession {
+        UploadSession {
+            id: "0".to_owned(),
+            name: "video.mov".to_owned(),
+            size,
+            hash: None,
+            received: Vec::new(),
+            finalizing: false,
+        }
+    }
+
+    #[test]
+    fn merges_overlapping_and_adjacent_ranges() {
+        let mut session = session(100);
+        session.insert_range(0, 10);
+        session.insert_range(10, 20);
+        session.insert_range(15, 30);
+        assert_eq!(session.received, [(0, 30)]);
+    }
+
+    #[test]
+    fn keeps_gaps_between_ranges() {
+        let mut session = session(100);
+        session.insert_range(50, 60);
+        session.insert_range(0, 10);
+        session.insert_range(20, 30);
+        assert_eq!(session.received, [(0, 10), (20, 30), (50, 60)]);
+        assert_eq!(session.received_string(), "0-10,20-30,50-60");
+
+        session.insert_range(5, 55);
+        assert_eq!(session.received, [(0, 60)]);
+    }
+
+    #[test]
+    fn ignores_empty_chunks() {
+        let mut session = session(100);
+        session.insert_range(40, 40);
+        assert!(session.received.is_empty());
+        assert_eq!(session.received_string(), "");
+    }
+
+    #[test]
+    fn completes_once_every_byte_arrived() {
+        let mut session = session(100);
+        session.insert_range(0, 40);
+        assert!(!session.is_complete());
+
+        // A resumed upload re-sends from the end of the reported range.
+        session.insert_range(60, 100);
+        assert!(!session.is_complete());
+        session.insert_range(40, 60);
+        assert!(session.is_complete());
+        assert_eq!(session.received_string(), "0-100");
+    }
+
+    #[test]
+    fn completes_repeated_chunks_without_growing() {
+        let mut session = session(100);
+        session.insert_range(0, 100);
+        session.insert_range(0, 100);
+        session.insert_range(30, 70);
+        assert_eq!(session.received, [(0, 100)]);
+        assert!(session.is_complete());
+    }
+
+    #[test]
+    fn treats_empty_uploads_as_complete() {
+        assert!(session(0).is_complete());
+    }
+}
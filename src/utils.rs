@@ -3,14 +3,22 @@ use arboard::ImageData;
 use base64::Engine;
 use base64::engine::general_purpose;
 use env::current_exe;
-use image::{ImageBuffer, ImageFormat, Rgba};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageBuffer, ImageFormat, Rgba};
+use log::warn;
 use native_dialog::{DialogBuilder, MessageLevel};
+use notify_rust::{Notification, Timeout};
 use rouille::percent_encoding::percent_encode;
+use sha2::{Digest, Sha256};
 use single_instance::SingleInstance;
 use std::env;
-use std::fs::create_dir_all;
-use std::io::Cursor;
+use std::fs::{File, OpenOptions, copy, create_dir_all, remove_file, rename};
+use std::io::{self, Cursor};
 use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 pub fn resolve_base_directory(dir_name: &str) -> Result<PathBuf> {
     match dir_name {
@@ -24,43 +32,188 @@ pub fn resolve_base_directory(dir_name: &str) -> Result<PathBuf> {
     .with_context(|| format!("Could not find the {:?} directory.", dir_name))
 }
 
-pub fn create_unique_file_path<P: AsRef<Path>>(base_dir: P, file_name: &str) -> Result<PathBuf> {
+pub fn move_file<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<()> {
+    match rename(&from, &to) {
+        Err(error) if error.kind() == io::ErrorKind::CrossesDevices => {
+            copy(&from, &to)?;
+            remove_file(&from)
+        }
+        result => result,
+    }
+}
+
+pub fn split_file_name(file_name: &str) -> (&str, &str) {
+    let Some(dot) = file_name
+        .rfind('.')
+        .filter(|&dot| dot > 0 && dot + 1 < file_name.len())
+    else {
+        return (file_name, "");
+    };
+    let (stem, extension) = file_name.split_at(dot);
+    if let Some(inner_dot) = stem.rfind('.').filter(|&inner_dot| inner_dot > 0)
+        && stem[inner_dot + 1..].eq_ignore_ascii_case("tar")
+    {
+        return file_name.split_at(inner_dot);
+    }
+    (stem, extension)
+}
+
+pub fn format_unique_name(template: &str, file_name: &str, counter: u32) -> String {
+    let (stem, extension) = split_file_name(file_name);
+    let counter = counter.to_string();
+    let placeholders = [
+        ("{name}", stem),
+        ("{n}", counter.as_str()),
+        ("{ext}", extension),
+    ];
+
+    let mut unique_name = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        unique_name.push_str(&rest[..start]);
+        rest = &rest[start..];
+        match placeholders
+            .iter()
+            .find(|(placeholder, _)| rest.starts_with(placeholder))
+        {
+            Some((placeholder, value)) => {
+                unique_name.push_str(value);
+                rest = &rest[placeholder.len()..];
+            }
+            None => {
+                unique_name.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    unique_name.push_str(rest);
+    unique_name
+}
+
+pub fn create_unique_file_path<P: AsRef<Path>>(
+    base_dir: P,
+    file_name: &str,
+    template: &str,
+) -> Result<PathBuf> {
+    const MAX_ATTEMPTS: u32 = 10_000;
     create_dir_all(&base_dir)?;
 
-    let original_path = Path::new(file_name);
-    let mut unique_path = base_dir.as_ref().join(original_path);
-
-    if unique_path.exists() {
-        let name_stem = original_path
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .context("Invalid file name")?;
-        let extension = original_path
-            .extension()
-            .and_then(|s| s.to_str())
-            .unwrap_or("");
-
-        for i in 1.. {
-            let new_name = format!("{}({}).{}", name_stem, i, extension);
-            let temp_path = base_dir.as_ref().join(new_name);
-
-            if !temp_path.exists() {
-                unique_path = temp_path;
-                break;
+    for counter in 0..MAX_ATTEMPTS {
+        let candidate = match counter {
+            0 => file_name.to_owned(),
+            _ => format_unique_name(template, file_name, counter),
+        };
+        let unique_path = base_dir.as_ref().join(candidate);
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&unique_path)
+        {
+            Ok(_) => return Ok(unique_path),
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(error) => {
+                return Err(error)
+                    .with_context(|| format!("Failed to reserve file path: {:?}", unique_path));
             }
         }
     }
+    anyhow::bail!(
+        "No free file name for {:?} after {} attempts",
+        file_name,
+        MAX_ATTEMPTS
+    )
+}
+
+pub struct PartialFile {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl PartialFile {
+    pub fn create<P: AsRef<Path>>(base_dir: P) -> Result<Self> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        create_dir_all(&base_dir)?;
+
+        let path = base_dir.as_ref().join(format!(
+            ".airdroppro-{}-{}.part",
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .with_context(|| format!("Failed to create partial file at: {:?}", path))?;
+        Ok(Self {
+            path,
+            file: Some(file),
+        })
+    }
+
+    pub fn file(&mut self) -> &mut File {
+        self.file.as_mut().unwrap()
+    }
+
+    pub fn persist<P: AsRef<Path>>(mut self, target: P) -> Result<()> {
+        let file = self.file.take().unwrap();
+        file.sync_all()
+            .with_context(|| format!("Failed to flush partial file: {:?}", self.path))?;
+        drop(file);
+        rename(&self.path, &target).with_context(|| {
+            format!(
+                "Failed to move partial file {:?} to {:?}",
+                self.path,
+                target.as_ref()
+            )
+        })?;
+        self.path = PathBuf::new();
+        Ok(())
+    }
+}
+
+impl Drop for PartialFile {
+    fn drop(&mut self) {
+        self.file.take();
+        if !self.path.as_os_str().is_empty() {
+            remove_file(&self.path).ok();
+        }
+    }
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+pub fn sha256_file<P: AsRef<Path>>(path: P) -> Result<String> {
+    let mut file = File::open(&path)
+        .with_context(|| format!("Failed to open file for hashing: {:?}", path.as_ref()))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("Failed to hash file: {:?}", path.as_ref()))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
 
-    Ok(unique_path)
+pub fn get_config_dir() -> Result<PathBuf> {
+    let mut config_dir = dirs::config_dir().context("Failed to get standard config directory")?;
+    config_dir.push("AirDropPro");
+    create_dir_all(&config_dir)
+        .with_context(|| format!("Failed to create config path: {:?}", config_dir))?;
+    Ok(config_dir)
 }
 
 pub fn get_config_path() -> Result<PathBuf> {
-    let mut config_path = dirs::config_dir().context("Failed to get standard config directory")?;
-    config_path.push("AirDropPro");
-    create_dir_all(&config_path)
-        .with_context(|| format!("Failed to create config path: {:?}", config_path))?;
-    config_path.push("config.ini");
-    Ok(config_path)
+    Ok(get_config_dir()?.join("config.ini"))
 }
 
 pub fn base64_encode(input: &str) -> Result<String> {
@@ -76,7 +229,19 @@ pub fn base64_decode(input: &str) -> Result<String> {
     Ok(decoded_string)
 }
 
-pub fn encode_image_to_base64_png(image_data: ImageData) -> Result<String> {
+pub fn decode_base64_payload(input: &str) -> Result<Vec<u8>> {
+    let payload = match input.split_once(";base64,") {
+        Some((prefix, payload)) if prefix.starts_with("data:") => payload,
+        _ => input,
+    };
+    let payload: String = payload.split_whitespace().collect();
+    general_purpose::STANDARD
+        .decode(&payload)
+        .or_else(|_| general_purpose::URL_SAFE.decode(&payload))
+        .context("Failed to decode base64 payload.")
+}
+
+pub fn encode_image_to_png(image_data: &ImageData) -> Result<Vec<u8>> {
     let img_buf = ImageBuffer::<Rgba<u8>, _>::from_raw(
         image_data.width as u32,
         image_data.height as u32,
@@ -88,18 +253,175 @@ pub fn encode_image_to_base64_png(image_data: ImageData) -> Result<String> {
     img_buf
         .write_to(&mut cursor, ImageFormat::Png)
         .context("Failed to encode image to PNG format.")?;
-    let encoded_base64 = general_purpose::STANDARD.encode(&bytes);
-    Ok(encoded_base64)
+    Ok(bytes)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageEncoding {
+    Png,
+    Jpeg,
+    WebP,
+}
+
+impl ImageEncoding {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "png" => Some(Self::Png),
+            "jpg" | "jpeg" => Some(Self::Jpeg),
+            "webp" => Some(Self::WebP),
+            _ => None,
+        }
+    }
+
+    pub fn mime(self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Jpeg => "image/jpeg",
+            Self::WebP => "image/webp",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Jpeg => "jpg",
+            Self::WebP => "webp",
+        }
+    }
+}
+
+// Dimensions after scaling to `max_dimension` the way `encode_image` does, without decoding.
+pub fn scaled_dimensions(width: usize, height: usize, max_dimension: u32) -> (u64, u64) {
+    let (width, height) = (width as u64, height as u64);
+    let longest = width.max(height);
+    let max_dimension = u64::from(max_dimension);
+    if max_dimension == 0 || longest <= max_dimension {
+        return (width, height);
+    }
+    (
+        (width * max_dimension).div_ceil(longest).max(1),
+        (height * max_dimension).div_ceil(longest).max(1),
+    )
+}
+
+pub fn encode_image(
+    image_data: &ImageData,
+    encoding: ImageEncoding,
+    max_dimension: u32,
+    quality: u8,
+) -> Result<Vec<u8>> {
+    let img_buf = ImageBuffer::<Rgba<u8>, _>::from_raw(
+        image_data.width as u32,
+        image_data.height as u32,
+        image_data.bytes.to_vec(),
+    )
+    .context("Failed to create image buffer from raw data. The dimensions might be wrong.")?;
+    let mut image = DynamicImage::ImageRgba8(img_buf);
+    if max_dimension > 0 && (image.width() > max_dimension || image.height() > max_dimension) {
+        image = image.resize(max_dimension, max_dimension, FilterType::Lanczos3);
+    }
+
+    let mut bytes: Vec<u8> = Vec::new();
+    match encoding {
+        ImageEncoding::Png => image.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png),
+        ImageEncoding::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8())
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut bytes, quality)),
+        // The image crate only encodes lossless WebP, so `quality` does not apply.
+        ImageEncoding::WebP => image.write_with_encoder(WebPEncoder::new_lossless(&mut bytes)),
+    }
+    .with_context(|| format!("Failed to encode image to {:?} format.", encoding))?;
+    Ok(bytes)
+}
+
+pub fn base64_encode_bytes(bytes: &[u8]) -> String {
+    general_purpose::STANDARD.encode(bytes)
+}
+
+pub fn decode_image(bytes: &[u8]) -> Result<ImageData<'static>> {
+    let image = image::load_from_memory(bytes)
+        .context("Failed to decode image data.")?
+        .into_rgba8();
+    Ok(ImageData {
+        width: image.width() as usize,
+        height: image.height() as usize,
+        bytes: image.into_raw().into(),
+    })
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ByteRange {
+    Full,
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+pub fn parse_byte_range(header: &str, size: u64) -> ByteRange {
+    let Some(spec) = header.trim().strip_prefix("bytes=") else {
+        return ByteRange::Full;
+    };
+    let Some((start, end)) = spec.split_once('-') else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+
+    match (start.trim().parse::<u64>(), end.trim().parse::<u64>()) {
+        (Err(_), Ok(suffix)) if start.trim().is_empty() => {
+            if suffix == 0 || size == 0 {
+                ByteRange::Unsatisfiable
+            } else {
+                ByteRange::Partial(size.saturating_sub(suffix), size - 1)
+            }
+        }
+        (Ok(start), Err(_)) if end.trim().is_empty() => {
+            if start >= size {
+                ByteRange::Unsatisfiable
+            } else {
+                ByteRange::Partial(start, size - 1)
+            }
+        }
+        (Ok(start), Ok(end)) if start <= end => {
+            if start >= size {
+                ByteRange::Unsatisfiable
+            } else {
+                ByteRange::Partial(start, end.min(size - 1))
+            }
+        }
+        _ => ByteRange::Full,
+    }
 }
 
 pub fn clean_path_string(path: &str) -> &str {
     path.trim_end_matches(&['\r', '\n', '\u{0020}'][..])
 }
 
+pub fn constant_time_eq(left: &[u8], right: &[u8]) -> bool {
+    if left.len() != right.len() {
+        return false;
+    }
+    left.iter()
+        .zip(right)
+        .fold(0u8, |diff, (l, r)| diff | (l ^ r))
+        == 0
+}
+
 pub fn url_encode(input: &str) -> String {
     percent_encode(input.as_bytes(), rouille::DEFAULT_ENCODE_SET).to_string()
 }
 
+pub fn show_notification(summary: &str, body: &str) {
+    let shown = Notification::new()
+        .appname("app.airdroppro.desktop")
+        .summary(summary)
+        .body(body)
+        .timeout(Timeout::Milliseconds(5000))
+        .show();
+    if let Err(error) = shown {
+        warn!("Failed to show notification {:?}: {}", summary, error);
+    }
+}
+
 pub fn set_auto_startup(status: bool) -> Result<()> {
     let app_path = current_exe().context("Failed to get current executable path")?;
     let app_path = app_path.to_str().context("Failed to get absolute path")?;
@@ -144,3 +466,158 @@ pub fn ensure_single_instance(instance_name: &str) -> Result<()> {
     let _lock: &'static SingleInstance = Box::leak(Box::new(instance));
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{read_dir, remove_dir_all};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("airdroppro-{}-{}", name, process::id()));
+        remove_dir_all(&dir).ok();
+        dir
+    }
+
+    #[test]
+    fn splits_extensions() {
+        assert_eq!(split_file_name("photo.jpg"), ("photo", ".jpg"));
+        assert_eq!(split_file_name("archive.tar.gz"), ("archive", ".tar.gz"));
+        assert_eq!(split_file_name("backup.2024.zip"), ("backup.2024", ".zip"));
+    }
+
+    #[test]
+    fn keeps_names_without_extension_whole() {
+        assert_eq!(split_file_name("README"), ("README", ""));
+        assert_eq!(split_file_name(".bashrc"), (".bashrc", ""));
+        assert_eq!(split_file_name("trailing."), ("trailing.", ""));
+        assert_eq!(split_file_name(".config.tar.gz"), (".config", ".tar.gz"));
+    }
+
+    #[test]
+    fn formats_unique_names_from_templates() {
+        let template = "{name}({n}){ext}";
+        assert_eq!(format_unique_name(template, "photo.jpg", 1), "photo(1).jpg");
+        assert_eq!(
+            format_unique_name(template, "archive.tar.gz", 2),
+            "archive(2).tar.gz"
+        );
+        assert_eq!(format_unique_name(template, "README", 3), "README(3)");
+        assert_eq!(format_unique_name(template, ".bashrc", 1), ".bashrc(1)");
+        assert_eq!(
+            format_unique_name("{n}-{name}{ext}", "photo.jpg", 7),
+            "7-photo.jpg"
+        );
+    }
+
+    #[test]
+    fn formats_templates_without_counter_literally() {
+        assert_eq!(
+            format_unique_name("{name} copy{ext}", "photo.jpg", 1),
+            "photo copy.jpg"
+        );
+        assert_eq!(
+            format_unique_name("{name}{other}{ext}", "photo.jpg", 1),
+            "photo{other}.jpg"
+        );
+        assert_eq!(format_unique_name("fixed", "photo.jpg", 1), "fixed");
+    }
+
+    #[test]
+    fn counts_up_until_a_name_is_free() {
+        let dir = temp_dir("unique-names");
+        let template = "{name}({n}){ext}";
+        let paths: Vec<PathBuf> = (0..3)
+            .map(|_| create_unique_file_path(&dir, "photo.jpg", template).unwrap())
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                dir.join("photo.jpg"),
+                dir.join("photo(1).jpg"),
+                dir.join("photo(2).jpg")
+            ]
+        );
+
+        remove_file(dir.join("photo(1).jpg")).unwrap();
+        assert_eq!(
+            create_unique_file_path(&dir, "photo.jpg", template).unwrap(),
+            dir.join("photo(1).jpg")
+        );
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn gives_up_when_the_template_repeats_a_name() {
+        let dir = temp_dir("repeated-names");
+        let template = "{name} copy{ext}";
+        create_unique_file_path(&dir, "photo.jpg", template).unwrap();
+        create_unique_file_path(&dir, "photo.jpg", template).unwrap();
+        assert!(create_unique_file_path(&dir, "photo.jpg", template).is_err());
+        assert_eq!(read_dir(&dir).unwrap().count(), 2);
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parses_open_ended_ranges() {
+        assert_eq!(
+            parse_byte_range("bytes=0-", 1000),
+            ByteRange::Partial(0, 999)
+        );
+        assert_eq!(
+            parse_byte_range("bytes=400-", 1000),
+            ByteRange::Partial(400, 999)
+        );
+    }
+
+    #[test]
+    fn parses_suffix_ranges() {
+        assert_eq!(
+            parse_byte_range("bytes=-500", 1000),
+            ByteRange::Partial(500, 999)
+        );
+        assert_eq!(
+            parse_byte_range("bytes=-5000", 1000),
+            ByteRange::Partial(0, 999)
+        );
+        assert_eq!(parse_byte_range("bytes=-0", 1000), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn ignores_reversed_and_malformed_ranges() {
+        assert_eq!(parse_byte_range("bytes=500-100", 1000), ByteRange::Full);
+        assert_eq!(parse_byte_range("bytes=0-10,20-30", 1000), ByteRange::Full);
+        assert_eq!(parse_byte_range("items=0-10", 1000), ByteRange::Full);
+        assert_eq!(parse_byte_range("bytes=abc", 1000), ByteRange::Full);
+    }
+
+    #[test]
+    fn rejects_ranges_starting_past_the_end() {
+        assert_eq!(
+            parse_byte_range("bytes=1000-", 1000),
+            ByteRange::Unsatisfiable
+        );
+        assert_eq!(
+            parse_byte_range("bytes=1500-2000", 1000),
+            ByteRange::Unsatisfiable
+        );
+    }
+
+    #[test]
+    fn clamps_the_end_to_the_size() {
+        assert_eq!(
+            parse_byte_range("bytes=900-5000", 1000),
+            ByteRange::Partial(900, 999)
+        );
+        assert_eq!(
+            parse_byte_range("bytes=0-999", 1000),
+            ByteRange::Partial(0, 999)
+        );
+    }
+
+    #[test]
+    fn has_no_satisfiable_range_in_an_empty_file() {
+        assert_eq!(parse_byte_range("bytes=0-", 0), ByteRange::Unsatisfiable);
+        assert_eq!(parse_byte_range("bytes=-500", 0), ByteRange::Unsatisfiable);
+        assert_eq!(parse_byte_range("bytes=0-10", 0), ByteRange::Unsatisfiable);
+    }
+}